@@ -0,0 +1,143 @@
+use gdk;
+use gdk::ModifierType as Mod;
+
+/// What a key binding does
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Copy,
+    Paste,
+    /// Pastes the primary selection (the last selected text) instead of the clipboard
+    PastePrimary,
+    ZoomIn,
+    ZoomOut,
+    ResetZoom,
+    SendBytes(Vec<u8>),
+    ScrollPageUp,
+    ScrollPageDown,
+    ScrollToTop,
+    ScrollToBottom,
+    /// Clears the screen and the scrollback
+    ClearScrollback,
+    /// Selects the screen and the scrollback
+    SelectAll,
+    /// Copies if something is selected, otherwise sends the bytes (e.g. Ctrl+C's 0x03)
+    CopyOrSend(Vec<u8>),
+}
+
+/// Which shortcuts copy and paste, on top of the ones in the bindings
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CopyPasteMode {
+    /// Ctrl+Shift+C and Ctrl+Shift+V only, so Ctrl+C and Ctrl+V always go to the program
+    CtrlShift,
+    /// Also Ctrl+C and Ctrl+V, like in other apps: Ctrl+C copies (and deselects) if something
+    /// is selected and interrupts the program (sends 0x03) otherwise, Ctrl+V always pastes
+    Ctrl,
+}
+
+impl Default for CopyPasteMode {
+    fn default() -> CopyPasteMode {
+        CopyPasteMode::CtrlShift
+    }
+}
+
+/// A key combination that triggers an `Action` instead of being sent to the terminal
+#[derive(Clone, Debug)]
+pub struct KeyBinding {
+    pub keyval: u32,
+    pub mods: Mod,
+    pub action: Action,
+}
+
+impl KeyBinding {
+    pub fn new(keyval: u32, mods: Mod, action: Action) -> KeyBinding {
+        KeyBinding { keyval: gdk::keyval_to_lower(keyval), mods, action }
+    }
+
+    /// Whether a key press matches, ignoring lock modifiers (Caps Lock, Num Lock etc.)
+    pub fn matches(&self, keyval: u32, mods: Mod) -> bool {
+        let mods = mods & (Mod::SHIFT_MASK | Mod::CONTROL_MASK | Mod::MOD1_MASK | Mod::SUPER_MASK);
+        self.keyval == gdk::keyval_to_lower(keyval) && self.mods == mods
+    }
+}
+
+/// The extra bindings for a `CopyPasteMode`, checked after the regular ones
+pub fn copy_paste_bindings(mode: CopyPasteMode) -> Vec<KeyBinding> {
+    use gdk::enums::key;
+    match mode {
+        CopyPasteMode::CtrlShift => Vec::new(),
+        CopyPasteMode::Ctrl => vec![
+            KeyBinding::new(key::c, Mod::CONTROL_MASK, Action::CopyOrSend(vec![0x03])),
+            KeyBinding::new(key::v, Mod::CONTROL_MASK, Action::Paste),
+        ],
+    }
+}
+
+/// Bindings for telling Shift+Enter and Ctrl+Enter apart from Enter, which always sends `\r`
+/// (so do the modified ones without these), e.g. `enter_bindings(b"\n", b"\x1b[13;5u")`.
+///
+/// Which sequences a program understands is up to the program, so these aren't bound by default.
+pub fn enter_bindings(shift: &[u8], ctrl: &[u8]) -> Vec<KeyBinding> {
+    use gdk::enums::key;
+    let mut bindings = Vec::with_capacity(4);
+    for &keyval in &[key::Return, key::KP_Enter] {
+        bindings.push(KeyBinding::new(keyval, Mod::SHIFT_MASK, Action::SendBytes(shift.to_vec())));
+        bindings.push(KeyBinding::new(keyval, Mod::CONTROL_MASK, Action::SendBytes(ctrl.to_vec())));
+    }
+    bindings
+}
+
+pub fn default_bindings() -> Vec<KeyBinding> {
+    use gdk::enums::key;
+    let ctrl = Mod::CONTROL_MASK;
+    vec![
+        KeyBinding::new(key::c, ctrl | Mod::SHIFT_MASK, Action::Copy),
+        KeyBinding::new(key::v, ctrl | Mod::SHIFT_MASK, Action::Paste),
+        KeyBinding::new(key::Insert, Mod::SHIFT_MASK, Action::PastePrimary),
+        KeyBinding::new(key::Insert, ctrl, Action::Copy),
+        KeyBinding::new(key::Page_Up, Mod::SHIFT_MASK, Action::ScrollPageUp),
+        KeyBinding::new(key::Page_Down, Mod::SHIFT_MASK, Action::ScrollPageDown),
+        KeyBinding::new(key::Home, Mod::SHIFT_MASK, Action::ScrollToTop),
+        KeyBinding::new(key::End, Mod::SHIFT_MASK, Action::ScrollToBottom),
+        KeyBinding::new(key::k, ctrl | Mod::SHIFT_MASK, Action::ClearScrollback),
+        KeyBinding::new(key::a, ctrl | Mod::SHIFT_MASK, Action::SelectAll),
+        KeyBinding::new(key::plus, ctrl, Action::ZoomIn),
+        KeyBinding::new(key::plus, ctrl | Mod::SHIFT_MASK, Action::ZoomIn),
+        KeyBinding::new(key::equal, ctrl, Action::ZoomIn),
+        KeyBinding::new(key::KP_Add, ctrl, Action::ZoomIn),
+        KeyBinding::new(key::minus, ctrl, Action::ZoomOut),
+        KeyBinding::new(key::KP_Subtract, ctrl, Action::ZoomOut),
+        KeyBinding::new(key::_0, ctrl, Action::ResetZoom),
+        KeyBinding::new(key::KP_0, ctrl, Action::ResetZoom),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use gdk::enums::key;
+
+    use super::*;
+
+    #[test]
+    fn matching_ignores_case_and_lock_modifiers() {
+        let binding = KeyBinding::new(key::c, Mod::CONTROL_MASK | Mod::SHIFT_MASK, Action::Copy);
+        // with Shift held, GDK reports the uppercase keyval
+        assert!(binding.matches(key::C, Mod::CONTROL_MASK | Mod::SHIFT_MASK));
+        assert!(binding.matches(key::c, Mod::CONTROL_MASK | Mod::SHIFT_MASK | Mod::LOCK_MASK | Mod::MOD2_MASK));
+    }
+
+    #[test]
+    fn matching_needs_the_exact_modifiers() {
+        let binding = KeyBinding::new(key::c, Mod::CONTROL_MASK | Mod::SHIFT_MASK, Action::Copy);
+        assert!(!binding.matches(key::c, Mod::CONTROL_MASK));
+        assert!(!binding.matches(key::c, Mod::CONTROL_MASK | Mod::SHIFT_MASK | Mod::MOD1_MASK));
+        assert!(!binding.matches(key::v, Mod::CONTROL_MASK | Mod::SHIFT_MASK));
+    }
+
+    #[test]
+    fn default_bindings_cover_the_usual_shortcuts() {
+        let action = |keyval, mods| default_bindings().into_iter().find(|b: &KeyBinding| b.matches(keyval, mods)).map(|b| b.action);
+        assert_eq!(action(key::Insert, Mod::SHIFT_MASK), Some(Action::PastePrimary));
+        assert_eq!(action(key::plus, Mod::CONTROL_MASK | Mod::SHIFT_MASK), Some(Action::ZoomIn));
+        assert_eq!(action(key::c, Mod::CONTROL_MASK), None);
+    }
+}
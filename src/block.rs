@@ -0,0 +1,123 @@
+use alacritty::grid::Grid;
+use alacritty::index::{Column, Point};
+use alacritty::term::cell::{Cell, Flags};
+
+use crate::overlay::Overlay;
+
+/// A rectangular selection between two corners, as buffer points (lines counted from
+/// the bottom of the buffer, like `Term::visible_to_buffer` gives them).
+///
+/// Alacritty's selections flow from line to line, so this one is the widget's own:
+/// it's highlighted by inverting its cells just for a frame, like hovered URLs are underlined.
+/// It's dropped when the grid is resized or the program prints (which may scroll it),
+/// as its buffer lines would point at other text then. Corners outside of the grid
+/// (e.g. after the window narrowed) are clamped to it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BlockSelection {
+    pub start: Point<usize>,
+    pub end: Point<usize>,
+}
+
+impl BlockSelection {
+    pub fn new(point: Point<usize>) -> BlockSelection {
+        BlockSelection { start: point, end: point }
+    }
+
+    pub fn update(&mut self, point: Point<usize>) {
+        self.end = point;
+    }
+
+    /// Just clicked, not dragged yet
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// The bottom and the top buffer line, within the grid
+    fn lines(&self, grid: &Grid<Cell>) -> (usize, usize) {
+        let last = grid.len() - 1;
+        (self.start.line.min(self.end.line).min(last), self.start.line.max(self.end.line).min(last))
+    }
+
+    /// The leftmost and the rightmost column, within the grid
+    fn cols(&self, grid: &Grid<Cell>) -> (usize, usize) {
+        let last = grid.num_cols().0 - 1;
+        (self.start.col.0.min(self.end.col.0).min(last), self.start.col.0.max(self.end.col.0).min(last))
+    }
+
+    /// The selected columns of each line, top to bottom, with trailing whitespace trimmed
+    pub fn text(&self, grid: &Grid<Cell>) -> String {
+        let (bottom, top) = self.lines(grid);
+        let (left, right) = self.cols(grid);
+        let rows = (bottom..=top).rev().map(|line| {
+            let row = &grid[line];
+            let text = (left..=right).map(|col| &row[Column(col)])
+                .filter(|cell| !cell.flags.contains(Flags::WIDE_CHAR_SPACER))
+                .map(|cell| cell.c)
+                .collect::<String>();
+            text.trim_end().to_owned()
+        });
+        rows.collect::<Vec<_>>().join("\n")
+    }
+
+    /// Inverts the selected cells that are on the screen for the frame
+    pub fn highlight(&self, grid: &mut Grid<Cell>, overlay: &mut Overlay) {
+        let (bottom, top) = self.lines(grid);
+        let (left, right) = self.cols(grid);
+        let offset = grid.display_offset();
+        let visible_top = offset + grid.num_lines().0 - 1;
+        for line in bottom.max(offset)..=top.min(visible_top) {
+            for col in left..=right {
+                overlay.modify(grid, line, Column(col), |cell| cell.flags.toggle(Flags::INVERSE));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alacritty::grid::Grid;
+    use alacritty::index::{Column, Line, Point};
+    use alacritty::term::cell::{Cell, Flags};
+
+    use crate::overlay::Overlay;
+    use super::BlockSelection;
+
+    /// A grid showing `rows`, without scrollback
+    fn grid(rows: &[&str]) -> Grid<Cell> {
+        let cols = rows.iter().map(|row| row.len()).max().unwrap_or(1);
+        let mut grid = Grid::new(Line(rows.len()), Column(cols), 0, Cell::default());
+        for (i, row) in rows.iter().enumerate() {
+            for (col, c) in row.chars().enumerate() {
+                grid[rows.len() - 1 - i][Column(col)].c = c;
+            }
+        }
+        grid
+    }
+
+    fn block(start: (usize, usize), end: (usize, usize)) -> BlockSelection {
+        let mut block = BlockSelection::new(Point { line: start.0, col: Column(start.1) });
+        block.update(Point { line: end.0, col: Column(end.1) });
+        block
+    }
+
+    #[test]
+    fn text_is_the_selected_columns() {
+        let grid = grid(&["abcd", "efgh", "ijkl"]);
+        // buffer lines count from the bottom, so this is rows 0 to 1, columns 1 to 2
+        assert_eq!(block((2, 2), (1, 1)).text(&grid), "bc\nfg");
+    }
+
+    #[test]
+    fn corners_outside_the_grid_are_clamped() {
+        // e.g. selected before the window got narrower and shorter
+        let grid = grid(&["abcd", "efgh"]);
+        assert_eq!(block((5, 2), (0, 10)).text(&grid), "cd\ngh");
+        let mut grid = grid;
+        let mut overlay = Overlay::new();
+        block((5, 2), (0, 10)).highlight(&mut grid, &mut overlay);
+        assert!(grid[1][Column(3)].flags.contains(Flags::INVERSE));
+        assert!(!grid[1][Column(1)].flags.contains(Flags::INVERSE));
+        overlay.undo(&mut grid);
+        assert!(!grid[1][Column(3)].flags.contains(Flags::INVERSE));
+    }
+}
@@ -0,0 +1,254 @@
+use alacritty::Rgb;
+use alacritty::ansi::{Color, NamedColor};
+use alacritty::grid::Grid;
+use alacritty::index::Column;
+use alacritty::term::cell::Cell;
+
+use crate::overlay::Overlay;
+
+/// How many colors the terminal shows.
+///
+/// The renderer itself always does 24-bit color, the other modes map the colors programs
+/// ask for down to the xterm palette (like a terminal that doesn't support more would),
+/// for matching the look of other setups.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorMode {
+    Truecolor,
+    Palette256,
+    Palette8,
+}
+
+impl Default for ColorMode {
+    fn default() -> ColorMode {
+        ColorMode::Truecolor
+    }
+}
+
+/// The steps of the xterm 6x6x6 color cube
+const CUBE_STEPS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+fn nearest_cube_step(value: u8) -> usize {
+    (0..CUBE_STEPS.len()).min_by_key(|&i| (i32::from(CUBE_STEPS[i]) - i32::from(value)).abs()).unwrap_or(0)
+}
+
+fn distance(a: Rgb, b: Rgb) -> i32 {
+    let d = |x: u8, y: u8| (i32::from(x) - i32::from(y)).pow(2);
+    d(a.r, b.r) + d(a.g, b.g) + d(a.b, b.b)
+}
+
+/// The RGB value xterm uses for a color past the first 16
+fn xterm_rgb(index: u8) -> Rgb {
+    if index >= 232 {
+        let v = 8 + (index - 232) * 10;
+        return Rgb { r: v, g: v, b: v };
+    }
+    let i = index.saturating_sub(16);
+    Rgb { r: CUBE_STEPS[(i / 36) as usize], g: CUBE_STEPS[(i / 6 % 6) as usize], b: CUBE_STEPS[(i % 6) as usize] }
+}
+
+/// The closest color of the cube or of the grey ramp
+fn nearest_256(rgb: Rgb) -> u8 {
+    let (r, g, b) = (nearest_cube_step(rgb.r), nearest_cube_step(rgb.g), nearest_cube_step(rgb.b));
+    let cube = (16 + 36 * r + 6 * g + b) as u8;
+    let average = (u16::from(rgb.r) + u16::from(rgb.g) + u16::from(rgb.b)) / 3;
+    let grey = (232 + (average.saturating_sub(3) / 10).min(23)) as u8;
+    if distance(xterm_rgb(grey), rgb) < distance(xterm_rgb(cube), rgb) { grey } else { cube }
+}
+
+/// The 8 basic colors, in palette order
+const BASIC_COLORS: [NamedColor; 8] = [
+    NamedColor::Black, NamedColor::Red, NamedColor::Green, NamedColor::Yellow,
+    NamedColor::Blue, NamedColor::Magenta, NamedColor::Cyan, NamedColor::White,
+];
+
+/// The closest of the 8 basic colors, whose bits are red, green and blue
+fn nearest_8(rgb: Rgb) -> NamedColor {
+    let bits = (rgb.r > 127) as usize | ((rgb.g > 127) as usize) << 1 | ((rgb.b > 127) as usize) << 2;
+    BASIC_COLORS[bits]
+}
+
+/// The basic color a bright one is the bright version of, other colors as they are
+fn basic_color(color: NamedColor) -> NamedColor {
+    match color {
+        NamedColor::BrightBlack => NamedColor::Black,
+        NamedColor::BrightRed => NamedColor::Red,
+        NamedColor::BrightGreen => NamedColor::Green,
+        NamedColor::BrightYellow => NamedColor::Yellow,
+        NamedColor::BrightBlue => NamedColor::Blue,
+        NamedColor::BrightMagenta => NamedColor::Magenta,
+        NamedColor::BrightCyan => NamedColor::Cyan,
+        NamedColor::BrightWhite => NamedColor::White,
+        _ => color,
+    }
+}
+
+fn map_color(color: Color, mode: ColorMode) -> Color {
+    match (mode, color) {
+        (ColorMode::Truecolor, _) => color,
+        (ColorMode::Palette256, Color::Spec(rgb)) => Color::Indexed(nearest_256(rgb)),
+        (ColorMode::Palette8, Color::Spec(rgb)) => Color::Named(nearest_8(rgb)),
+        // the bright colors (from SGR 90-97 and 100-107, or by index) become the basic ones
+        (ColorMode::Palette8, Color::Named(named)) => Color::Named(basic_color(named)),
+        (ColorMode::Palette8, Color::Indexed(i)) if i < 8 => color,
+        (ColorMode::Palette8, Color::Indexed(i)) if i < 16 => Color::Named(BASIC_COLORS[(i - 8) as usize]),
+        (ColorMode::Palette8, Color::Indexed(i)) => Color::Named(nearest_8(xterm_rgb(i))),
+        _ => color,
+    }
+}
+
+/// Maps the colors of the visible cells for the given mode, for the frame
+pub fn downsample(grid: &mut Grid<Cell>, mode: ColorMode, overlay: &mut Overlay) {
+    if mode == ColorMode::Truecolor {
+        return;
+    }
+    let offset = grid.display_offset();
+    let cols = grid.num_cols().0;
+    for line in offset..offset + grid.num_lines().0 {
+        for col in 0..cols {
+            let cell = &grid[line][Column(col)];
+            let (fg, bg) = (map_color(cell.fg, mode), map_color(cell.bg, mode));
+            if fg != cell.fg || bg != cell.bg {
+                overlay.modify(grid, line, Column(col), |cell| {
+                    cell.fg = fg;
+                    cell.bg = bg;
+                });
+            }
+        }
+    }
+}
+
+/// Black or white, whichever is easier to read on the given color
+pub fn contrasting(rgb: Rgb) -> Rgb {
+    let luma = 299 * u32::from(rgb.r) + 587 * u32::from(rgb.g) + 114 * u32::from(rgb.b);
+    if luma > 128_000 { Rgb { r: 0, g: 0, b: 0 } } else { Rgb { r: 255, g: 255, b: 255 } }
+}
+
+/// The names of the 16 ANSI colors, as used in color schemes
+const NAMES: [&str; 16] = [
+    "black", "red", "green", "yellow", "blue", "magenta", "cyan", "white",
+    "bright_black", "bright_red", "bright_green", "bright_yellow",
+    "bright_blue", "bright_magenta", "bright_cyan", "bright_white",
+];
+
+/// Colors to use instead of the config's, the ones that aren't set are kept
+#[derive(Clone, Debug, Default)]
+pub struct ColorScheme {
+    pub foreground: Option<Rgb>,
+    pub background: Option<Rgb>,
+    pub cursor: Option<Rgb>,
+    /// The 8 normal colors, then the 8 bright ones
+    pub palette: [Option<Rgb>; 16],
+}
+
+/// Parses a `#rrggbb` color
+pub fn parse_rgb(s: &str) -> Option<Rgb> {
+    let hex = s.trim().trim_start_matches('#');
+    if hex.len() != 6 || !hex.is_ascii() {
+        return None;
+    }
+    let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+    Some(Rgb { r: channel(0)?, g: channel(2)?, b: channel(4)? })
+}
+
+impl ColorScheme {
+    /// Sets a color by name: `foreground`, `background`, `cursor`, one of the ANSI colors
+    /// (`red`, `bright_red`, ...) or `color0` to `color15`
+    pub fn set(&mut self, name: &str, rgb: Rgb) -> Result<(), String> {
+        let name = name.trim().to_lowercase();
+        match &*name {
+            "foreground" => self.foreground = Some(rgb),
+            "background" => self.background = Some(rgb),
+            "cursor" => self.cursor = Some(rgb),
+            _ => {
+                let index = NAMES.iter().position(|&n| n == name)
+                    .or_else(|| name.trim_start_matches("color").parse().ok().filter(|&i: &usize| name.starts_with("color") && i < 16))
+                    .ok_or_else(|| format!("Unknown color {:?}", name))?;
+                self.palette[index] = Some(rgb);
+            },
+        }
+        Ok(())
+    }
+
+    /// Builds a scheme from (name, `#rrggbb`) pairs, see `set` for the names
+    pub fn from_map<'a, I: IntoIterator<Item = (&'a str, &'a str)>>(colors: I) -> Result<ColorScheme, String> {
+        let mut scheme = ColorScheme::default();
+        for (name, value) in colors {
+            let rgb = parse_rgb(value).ok_or_else(|| format!("Invalid color {:?} for {}", value, name))?;
+            scheme.set(name, rgb)?;
+        }
+        Ok(scheme)
+    }
+
+    /// Parses a scheme with one `name = #rrggbb` per line (blank lines and `//` comments are skipped)
+    pub fn parse(text: &str) -> Result<ColorScheme, String> {
+        let lines = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with("//"));
+        let pairs = lines.map(|line| {
+            let mut parts = line.splitn(2, '=');
+            match (parts.next(), parts.next()) {
+                (Some(name), Some(value)) => Ok((name.trim(), value.trim())),
+                _ => Err(format!("Expected name = #rrggbb, got {:?}", line)),
+            }
+        }).collect::<Result<Vec<_>, _>>()?;
+        ColorScheme::from_map(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alacritty::Rgb;
+    use alacritty::ansi::{Color, NamedColor};
+    use alacritty::index::Column;
+
+    use crate::headless::HeadlessTerminal;
+    use crate::overlay::Overlay;
+    use super::*;
+
+    fn red(r: u8) -> Color {
+        Color::Spec(Rgb { r, g: 0, b: 0 })
+    }
+
+    #[test]
+    fn truecolor_gradients_keep_every_color() {
+        let mut terminal = HeadlessTerminal::new(16, 1);
+        for i in 0..16u8 {
+            terminal.feed(format!("\x1b[38;2;{};0;0mX", i * 16).as_bytes());
+        }
+        let row = &terminal.term().grid()[0];
+        for i in 0..16 {
+            assert_eq!(row[Column(i)].fg, red(i as u8 * 16));
+        }
+    }
+
+    #[test]
+    fn colors_map_to_the_nearest_palette_entry() {
+        assert_eq!(map_color(red(255), ColorMode::Palette256), Color::Indexed(196));
+        // greys go to the grey ramp rather than the cube
+        assert_eq!(map_color(Color::Spec(Rgb { r: 128, g: 128, b: 128 }), ColorMode::Palette256), Color::Indexed(244));
+        assert_eq!(map_color(red(255), ColorMode::Palette8), Color::Named(NamedColor::Red));
+        assert_eq!(map_color(Color::Indexed(9), ColorMode::Palette8), Color::Named(NamedColor::Red));
+        assert_eq!(map_color(Color::Indexed(3), ColorMode::Palette8), Color::Indexed(3));
+        assert_eq!(map_color(red(255), ColorMode::Truecolor), red(255));
+    }
+
+    #[test]
+    fn downsampling_is_undone_after_the_frame() {
+        let mut terminal = HeadlessTerminal::new(4, 1);
+        terminal.feed(b"\x1b[38;2;255;0;0mX");
+        let grid = terminal.term_mut().grid_mut();
+        let mut overlay = Overlay::new();
+        downsample(grid, ColorMode::Palette8, &mut overlay);
+        assert_eq!(grid[0][Column(0)].fg, Color::Named(NamedColor::Red));
+        overlay.undo(grid);
+        assert_eq!(grid[0][Column(0)].fg, red(255));
+    }
+
+    #[test]
+    fn bright_colors_are_basic_in_8_color_mode() {
+        let mut terminal = HeadlessTerminal::new(4, 1);
+        terminal.feed(b"\x1b[91;104mX");
+        let grid = terminal.term_mut().grid_mut();
+        downsample(grid, ColorMode::Palette8, &mut Overlay::new());
+        assert_eq!(grid[0][Column(0)].fg, Color::Named(NamedColor::Red));
+        assert_eq!(grid[0][Column(0)].bg, Color::Named(NamedColor::Blue));
+    }
+}
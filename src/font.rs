@@ -3,6 +3,21 @@ use pango::prelude::*;
 
 use alacritty::config::{Font, Size};
 
+/// Checks whether Pango knows a font family with the given name
+pub fn family_exists(ctx: &pango::Context, family: &str) -> bool {
+    ctx.list_families().iter().any(|f| f.get_name().map_or(false, |name| name.eq_ignore_ascii_case(family)))
+}
+
+/// Makes an Alacritty font spec using the given family for all the faces
+pub fn from_family(family: &str, size: f32) -> Font {
+    let mut newf = Font::default();
+    newf.size = Size::new(size);
+    newf.normal.family = family.to_owned();
+    newf.bold.family = family.to_owned();
+    newf.italic.family = family.to_owned();
+    newf
+}
+
 /// Converts a Pango/Gtk font spec to an Alacritty one
 pub fn to_alacritty(fam: pango::FontFamily, size: i32) -> Font {
     let size = size as f32 / pango::SCALE as f32;
@@ -16,23 +31,105 @@ pub fn to_alacritty(fam: pango::FontFamily, size: i32) -> Font {
     } else {
         warn!("You've managed to select a font family with no name, somehow.");
     }
-    // Find exact names of "Normal" "Bold" "Italic" suffixes for this family
-    for face in fam.list_faces().iter() {
-        if let Some(desc) = face.describe() {
-            info!("  - has face {:?} style {:?} weight {:?} variant {:?}", face.get_face_name(), desc.get_style(), desc.get_weight(), desc.get_variant());
-            match (desc.get_style(), desc.get_weight()) {
-                (Style::Normal, Weight::Normal) => {
-                    newf.normal.style = face.get_face_name();
-                },
-                (Style::Normal, Weight::Bold) => {
-                    newf.bold.style = face.get_face_name();
-                },
-                (Style::Italic, Weight::Normal) => {
-                    newf.italic.style = face.get_face_name();
-                },
-                _ => (),
-            }
+    let faces = fam.list_faces().into_iter().filter_map(|face| {
+        let desc = face.describe()?;
+        info!("  - has face {:?} style {:?} weight {:?} variant {:?}", face.get_face_name(), desc.get_style(), desc.get_weight(), desc.get_variant());
+        Some((face.get_face_name(), desc.get_style(), desc.get_weight()))
+    });
+    let styles = pick_styles(faces);
+    newf.normal.style = styles.normal.or(newf.normal.style);
+    newf.bold.style = styles.bold.or(newf.bold.style);
+    newf.italic.style = styles.italic.or(newf.italic.style);
+    newf
+}
+
+/// The face names to use for each of Alacritty's styles, `None` where fontconfig should pick
+#[derive(Debug, Default, PartialEq)]
+struct Styles {
+    normal: Option<String>,
+    bold: Option<String>,
+    italic: Option<String>,
+}
+
+/// Finds exact names of "Normal" "Bold" "Italic" suffixes among a family's faces.
+///
+/// Faces that aren't quite that (e.g. only Semibold, or Oblique instead of Italic) are
+/// used if there's nothing better; if there's no such face at all, the style stays unset
+/// and fontconfig picks (or synthesizes) one by weight and slant.
+fn pick_styles<I: IntoIterator<Item = (Option<String>, Style, Weight)>>(faces: I) -> Styles {
+    let mut styles = Styles::default();
+    let mut bold_exact = false;
+    let mut bold_close = false;
+    let mut italic_exact = false;
+    for (name, style, weight) in faces {
+        match (style, weight) {
+            (Style::Normal, Weight::Normal) => {
+                styles.normal = name;
+            },
+            (Style::Normal, Weight::Bold) => {
+                styles.bold = name;
+                bold_exact = true;
+            },
+            (Style::Normal, weight) if is_bold(weight) && !bold_exact && !bold_close => {
+                styles.bold = name;
+                bold_close = true;
+            },
+            (Style::Italic, Weight::Normal) => {
+                styles.italic = name;
+                italic_exact = true;
+            },
+            (Style::Oblique, Weight::Normal) if !italic_exact => {
+                styles.italic = name;
+            },
+            _ => (),
         }
     }
-    newf
+    styles
+}
+
+fn is_bold(weight: Weight) -> bool {
+    match weight {
+        Weight::Semibold | Weight::Bold | Weight::Ultrabold | Weight::Heavy | Weight::Ultraheavy => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pango::{Style, Weight};
+
+    use super::{pick_styles, Styles};
+
+    fn face(name: &str, style: Style, weight: Weight) -> (Option<String>, Style, Weight) {
+        (Some(name.to_owned()), style, weight)
+    }
+
+    #[test]
+    fn exact_faces_win_whatever_the_order() {
+        let styles = pick_styles(vec![
+            face("Semibold", Style::Normal, Weight::Semibold),
+            face("Oblique", Style::Oblique, Weight::Normal),
+            face("Regular", Style::Normal, Weight::Normal),
+            face("Bold", Style::Normal, Weight::Bold),
+            face("Italic", Style::Italic, Weight::Normal),
+        ]);
+        assert_eq!(styles, Styles {
+            normal: Some("Regular".to_owned()),
+            bold: Some("Bold".to_owned()),
+            italic: Some("Italic".to_owned()),
+        });
+    }
+
+    #[test]
+    fn close_faces_stand_in_and_missing_ones_are_synthesized() {
+        let styles = pick_styles(vec![
+            face("Book", Style::Normal, Weight::Normal),
+            face("Heavy", Style::Normal, Weight::Heavy),
+            face("Oblique", Style::Oblique, Weight::Normal),
+        ]);
+        assert_eq!(styles.bold, Some("Heavy".to_owned()));
+        assert_eq!(styles.italic, Some("Oblique".to_owned()));
+        // left to fontconfig
+        assert_eq!(pick_styles(vec![face("Regular", Style::Normal, Weight::Normal)]).bold, None);
+    }
 }
@@ -0,0 +1,184 @@
+use std::io::{self, Write};
+
+use gdk::ModifierType as Mod;
+
+use alacritty::ansi::{CursorStyle, Processor};
+use alacritty::config::Config;
+use alacritty::term::{SizeInfo, Term, TermMode};
+
+use crate::{keys, text};
+
+// The private use chars the cursor shapes are drawn with, copied from Alacritty's font crate
+// (`UNDERLINE_CURSOR_CHAR`, `BEAM_CURSOR_CHAR` and `BOX_CURSOR_CHAR` in `font/src/lib.rs`),
+// which isn't our dependency
+const UNDERLINE_CURSOR_CHAR: char = '\u{10a3e2}';
+const BEAM_CURSOR_CHAR: char = '\u{10a3e3}';
+const BOX_CURSOR_CHAR: char = '\u{10a3e4}';
+
+/// A terminal without a window, GL context or child process, for testing escape sequence
+/// handling and key mapping: bytes go in as if a program printed them, and whatever the
+/// terminal would write back to the program (e.g. answers to queries) is collected.
+///
+/// The widget's state can't be made headless, its renderer needs a GL context.
+pub struct HeadlessTerminal {
+    config: Config,
+    terminal: Term,
+    parser: Processor,
+    replies: Vec<u8>,
+}
+
+impl HeadlessTerminal {
+    pub fn new(cols: usize, rows: usize) -> HeadlessTerminal {
+        let config = Config::default();
+        HeadlessTerminal {
+            terminal: Term::new(&config, size_info(cols, rows)),
+            config,
+            parser: Processor::new(),
+            replies: Vec::new(),
+        }
+    }
+
+    /// Processes output, as if the program in the terminal wrote it
+    pub fn feed(&mut self, bytes: &[u8]) {
+        let mut replies = Replies(&mut self.replies);
+        for &byte in bytes {
+            self.parser.advance(&mut self.terminal, byte, &mut replies);
+        }
+    }
+
+    /// Takes what the terminal wrote back since the last call
+    pub fn take_replies(&mut self) -> Vec<u8> {
+        self.replies.split_off(0)
+    }
+
+    /// The screen, one line per row, with trailing whitespace trimmed
+    pub fn visible_text(&self) -> String {
+        let grid = self.terminal.grid();
+        let offset = grid.display_offset();
+        text::lines_text(grid, (offset..offset + grid.num_lines().0).rev(), true)
+    }
+
+    pub fn mode(&self) -> TermMode {
+        *self.terminal.mode()
+    }
+
+    /// What pressing a non-character key sends in the current mode, see `keys::keyval_to_escape`
+    pub fn key(&self, keyval: u32, mods: Mod) -> Option<Vec<u8>> {
+        keys::keyval_to_escape(keyval, mods, self.mode())
+    }
+
+    /// The shape the renderer would draw the cursor with (for a focused widget)
+    pub fn cursor_shape(&self) -> CursorStyle {
+        // NOTE: the renderer draws these shapes as glyphs with private use chars,
+        // the block by inverting the cell instead
+        let shape = self.terminal.renderable_cells(&self.config, true).find_map(|cell| match cell.c {
+            UNDERLINE_CURSOR_CHAR => Some(CursorStyle::Underline),
+            BEAM_CURSOR_CHAR => Some(CursorStyle::Beam),
+            BOX_CURSOR_CHAR => Some(CursorStyle::HollowBlock),
+            _ => None,
+        });
+        shape.unwrap_or(CursorStyle::Block)
+    }
+
+    pub fn term(&self) -> &Term {
+        &self.terminal
+    }
+
+    pub fn term_mut(&mut self) -> &mut Term {
+        &mut self.terminal
+    }
+}
+
+/// A size with one pixel cells, so the size in pixels is the size in cells
+/// (e.g. for `tty::new`, which only passes the cells on to the PTY)
+pub fn size_info(cols: usize, rows: usize) -> SizeInfo {
+    SizeInfo {
+        width: cols as f32,
+        height: rows as f32,
+        cell_width: 1.0,
+        cell_height: 1.0,
+        padding_x: 0.0,
+        padding_y: 0.0,
+        dpr: 1.0,
+    }
+}
+
+/// Collects the terminal's replies instead of writing them to a PTY
+struct Replies<'a>(&'a mut Vec<u8>);
+
+impl<'a> Write for Replies<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gdk::enums::key;
+    use gdk::ModifierType as Mod;
+
+    use alacritty::ansi::CursorStyle;
+    use alacritty::index::Column;
+    use alacritty::term::cell::Flags;
+
+    use super::HeadlessTerminal;
+
+    #[test]
+    fn output_shows_on_the_screen() {
+        let mut terminal = HeadlessTerminal::new(10, 3);
+        terminal.feed(b"one\r\ntwo");
+        assert_eq!(terminal.visible_text(), "one\ntwo\n");
+    }
+
+    #[test]
+    fn cursor_keys_follow_decckm() {
+        let mut terminal = HeadlessTerminal::new(10, 3);
+        assert_eq!(terminal.key(key::Up, Mod::empty()), Some(b"\x1b[A".to_vec()));
+        terminal.feed(b"\x1b[?1h");
+        assert_eq!(terminal.key(key::Up, Mod::empty()), Some(b"\x1bOA".to_vec()));
+        terminal.feed(b"\x1b[?1l");
+        assert_eq!(terminal.key(key::Up, Mod::empty()), Some(b"\x1b[A".to_vec()));
+    }
+
+    #[test]
+    fn queries_are_answered() {
+        let mut terminal = HeadlessTerminal::new(10, 3);
+        terminal.feed(b"ab\x1b[6n");
+        assert_eq!(terminal.take_replies(), b"\x1b[1;3R".to_vec());
+        // taken, so there's nothing left
+        assert!(terminal.take_replies().is_empty());
+    }
+
+    #[test]
+    fn decscusr_changes_the_drawn_cursor() {
+        let mut terminal = HeadlessTerminal::new(10, 3);
+        assert_eq!(terminal.cursor_shape(), CursorStyle::Block);
+        terminal.feed(b"\x1b[4 q");
+        assert_eq!(terminal.cursor_shape(), CursorStyle::Underline);
+        terminal.feed(b"\x1b[6 q");
+        assert_eq!(terminal.cursor_shape(), CursorStyle::Beam);
+        // the blinking variants are drawn like the steady ones
+        terminal.feed(b"\x1b[3 q");
+        assert_eq!(terminal.cursor_shape(), CursorStyle::Underline);
+        // back to the config's
+        terminal.feed(b"\x1b[0 q");
+        assert_eq!(terminal.cursor_shape(), CursorStyle::Block);
+    }
+
+    #[test]
+    fn sgr_attributes_reach_the_cells() {
+        let mut terminal = HeadlessTerminal::new(10, 1);
+        terminal.feed(b"\x1b[1mb\x1b[0;3mi\x1b[0;4mu\x1b[0;9ms\x1b[0;2md");
+        let row = &terminal.term().grid()[0];
+        let attributes = Flags::BOLD | Flags::ITALIC | Flags::UNDERLINE | Flags::STRIKEOUT | Flags::DIM;
+        let expected = [Flags::BOLD, Flags::ITALIC, Flags::UNDERLINE, Flags::STRIKEOUT, Flags::DIM];
+        for (col, &flag) in expected.iter().enumerate() {
+            assert_eq!(row[Column(col)].flags & attributes, flag);
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use gdk::ModifierType as Mod;
+
+use alacritty::term::TermMode;
+
+/// xterm's modifier parameter (1 + shift + 2 * alt + 4 * ctrl), `None` without modifiers
+fn modifier_param(mods: Mod) -> Option<u8> {
+    let mut param = 0;
+    if mods.contains(Mod::SHIFT_MASK) {
+        param += 1;
+    }
+    if mods.intersects(Mod::MOD1_MASK | Mod::META_MASK) {
+        param += 2;
+    }
+    if mods.contains(Mod::CONTROL_MASK) {
+        param += 4;
+    }
+    if param == 0 { None } else { Some(param + 1) }
+}
+
+fn csi(c: char, m: Option<u8>) -> String {
+    match m {
+        Some(m) => format!("\x1b[1;{}{}", m, c),
+        None => format!("\x1b[{}", c),
+    }
+}
+
+/// Cursor keys, which switch to SS3 in application cursor mode (DECCKM)
+fn cursor(c: char, m: Option<u8>, app_cursor: bool) -> String {
+    if app_cursor && m.is_none() {
+        format!("\x1bO{}", c)
+    } else {
+        csi(c, m)
+    }
+}
+
+fn ss3(c: char, m: Option<u8>) -> String {
+    match m {
+        Some(m) => format!("\x1b[1;{}{}", m, c),
+        None => format!("\x1bO{}", c),
+    }
+}
+
+fn tilde(n: u8, m: Option<u8>) -> String {
+    match m {
+        Some(m) => format!("\x1b[{};{}~", n, m),
+        None => format!("\x1b[{}~", n),
+    }
+}
+
+/// Numeric keypad keys, which send SS3 sequences in application keypad mode (DECKPAM)
+/// and their plain characters otherwise
+fn keypad(keyval: u32, app_keypad: bool) -> Option<String> {
+    use gdk::enums::key::*;
+    #[allow(non_upper_case_globals)]
+    let (plain, app) = match keyval {
+        KP_0 => ("0", 'p'),
+        KP_1 => ("1", 'q'),
+        KP_2 => ("2", 'r'),
+        KP_3 => ("3", 's'),
+        KP_4 => ("4", 't'),
+        KP_5 => ("5", 'u'),
+        KP_6 => ("6", 'v'),
+        KP_7 => ("7", 'w'),
+        KP_8 => ("8", 'x'),
+        KP_9 => ("9", 'y'),
+        KP_Multiply => ("*", 'j'),
+        KP_Add => ("+", 'k'),
+        KP_Separator => (",", 'l'),
+        KP_Subtract => ("-", 'm'),
+        KP_Decimal => (".", 'n'),
+        KP_Divide => ("/", 'o'),
+        KP_Enter => ("\r", 'M'),
+        KP_Equal => ("=", 'X'),
+        _ => return None,
+    };
+    Some(if app_keypad { format!("\x1bO{}", app) } else { plain.to_owned() })
+}
+
+/// The SS3 sequence of an unmodified keypad key in application keypad mode.
+///
+/// Input methods commit the keypad's digits and operators as text, so the widget
+/// looks these up before the IM gets the key.
+pub fn application_keypad(keyval: u32, mods: Mod) -> Option<Vec<u8>> {
+    if modifier_param(mods).is_some() {
+        return None;
+    }
+    keypad(keyval, true).map(String::into_bytes)
+}
+
+/// Maps a non-character key (arrows, navigation, function and keypad keys) to its escape sequence
+pub fn keyval_to_escape(keyval: u32, mods: Mod, mode: TermMode) -> Option<Vec<u8>> {
+    use gdk::enums::key::*;
+    let m = modifier_param(mods);
+    let app_cursor = mode.contains(TermMode::APP_CURSOR);
+    if m.is_none() {
+        if let Some(seq) = keypad(keyval, mode.contains(TermMode::APP_KEYPAD)) {
+            return Some(seq.into_bytes());
+        }
+    }
+    #[allow(non_upper_case_globals)] // they're not mine, why complain here?!
+    let seq = match keyval {
+        Up | KP_Up => cursor('A', m, app_cursor),
+        Down | KP_Down => cursor('B', m, app_cursor),
+        Right | KP_Right => cursor('C', m, app_cursor),
+        Left | KP_Left => cursor('D', m, app_cursor),
+        Home | KP_Home => cursor('H', m, app_cursor),
+        End | KP_End => cursor('F', m, app_cursor),
+        Insert | KP_Insert => tilde(2, m),
+        Delete | KP_Delete => tilde(3, m),
+        Page_Up | KP_Page_Up => tilde(5, m),
+        Page_Down | KP_Page_Down => tilde(6, m),
+        Tab | ISO_Left_Tab if mods.contains(Mod::SHIFT_MASK) => "\x1b[Z".to_owned(),
+        F1 => ss3('P', m),
+        F2 => ss3('Q', m),
+        F3 => ss3('R', m),
+        F4 => ss3('S', m),
+        F5 => tilde(15, m),
+        F6 => tilde(17, m),
+        F7 => tilde(18, m),
+        F8 => tilde(19, m),
+        F9 => tilde(20, m),
+        F10 => tilde(21, m),
+        F11 => tilde(23, m),
+        F12 => tilde(24, m),
+        _ => return None,
+    };
+    Some(seq.into_bytes())
+}
+
+/// Encodes Backspace: DEL (0x7f) or, for programs and systems that expect it, BS (0x08).
+///
+/// Ctrl+Backspace erases a word with ^W (the tty's and readline's werase), and Alt prefixes
+/// ESC when `alt_escape` is set (readline's backward-kill-word).
+pub fn backspace(mods: Mod, sends_delete: bool, alt_escape: bool) -> Vec<u8> {
+    let byte = if mods.contains(Mod::CONTROL_MASK) {
+        0x17
+    } else if sends_delete {
+        0x7f
+    } else {
+        0x08
+    };
+    if alt_escape && mods.intersects(Mod::MOD1_MASK | Mod::META_MASK) {
+        vec![0x1b, byte]
+    } else {
+        vec![byte]
+    }
+}
+
+/// Encodes a key in the CSI u (fixterms, kitty) format, `ESC [ code ; modifiers u`,
+/// where the code is the Unicode codepoint of the unshifted key.
+///
+/// Only covers what the legacy encoding can't tell apart: characters with Ctrl or Alt held,
+/// and Enter, Tab, Backspace and Escape with any modifier. `None` for everything else.
+pub fn csi_u(keyval: u32, mods: Mod) -> Option<Vec<u8>> {
+    use gdk::enums::key::*;
+    let m = modifier_param(mods)?;
+    #[allow(non_upper_case_globals)]
+    let code = match keyval {
+        Return | KP_Enter => 13,
+        Tab | ISO_Left_Tab => 9,
+        BackSpace => 127,
+        Escape => 27,
+        _ if mods.intersects(Mod::CONTROL_MASK | Mod::MOD1_MASK | Mod::META_MASK) => {
+            let c = gdk::keyval_to_unicode(gdk::keyval_to_lower(keyval)).filter(|c| !c.is_control())?;
+            c as u32
+        },
+        _ => return None,
+    };
+    Some(format!("\x1b[{};{}u", code, m).into_bytes())
+}
+
+/// Encodes a typed character as UTF-8, with Ctrl masking ASCII letters and symbols
+/// down to their control codes (multibyte characters are sent as they are)
+pub fn char_input(c: char, ctrl: bool) -> Vec<u8> {
+    let mut buf = [0u8; 4];
+    let mut bytes = c.encode_utf8(&mut buf).as_bytes().to_vec();
+    if ctrl {
+        for ch in bytes.iter_mut() {
+            if *ch >= 0x40 && *ch < 0x80 {
+                *ch &= !0x60;
+            }
+        }
+    }
+    bytes
+}
+
+/// Computes the control character xterm generates for Ctrl + the given character
+pub fn control_code(c: char) -> Option<u8> {
+    match c {
+        ' ' | '2' => Some(0x00),
+        '3' => Some(0x1b),
+        '4' => Some(0x1c),
+        '5' => Some(0x1d),
+        '6' => Some(0x1e),
+        '7' | '/' => Some(0x1f),
+        '8' | '?' => Some(0x7f),
+        '@'..='~' => Some(c as u8 & 0x1f),
+        _ => None,
+    }
+}
+
+/// Encodes a character typed with Ctrl and/or Alt held, `None` if the modifiers don't change it
+pub fn modified_char(c: char, ctrl: bool, alt_escape: bool) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(5);
+    if alt_escape {
+        bytes.push(0x1b);
+    }
+    if ctrl {
+        bytes.push(control_code(c)?);
+    } else if alt_escape {
+        let mut buf = [0u8; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    } else {
+        return None;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gdk::enums::key;
+
+    fn escape(keyval: u32, mods: Mod, mode: TermMode) -> String {
+        String::from_utf8(keyval_to_escape(keyval, mods, mode).expect("no escape")).unwrap()
+    }
+
+    #[test]
+    fn cursor_keys_follow_decckm_unless_modified() {
+        assert_eq!(escape(key::Up, Mod::empty(), TermMode::empty()), "\x1b[A");
+        assert_eq!(escape(key::Up, Mod::empty(), TermMode::APP_CURSOR), "\x1bOA");
+        // with modifiers, it's always CSI with the modifier parameter
+        assert_eq!(escape(key::Left, Mod::CONTROL_MASK, TermMode::APP_CURSOR), "\x1b[1;5D");
+        assert_eq!(escape(key::Home, Mod::SHIFT_MASK, TermMode::empty()), "\x1b[1;2H");
+    }
+
+    #[test]
+    fn function_and_editing_keys() {
+        assert_eq!(escape(key::F1, Mod::empty(), TermMode::empty()), "\x1bOP");
+        assert_eq!(escape(key::F1, Mod::SHIFT_MASK, TermMode::empty()), "\x1b[1;2P");
+        assert_eq!(escape(key::F5, Mod::empty(), TermMode::empty()), "\x1b[15~");
+        assert_eq!(escape(key::F12, Mod::CONTROL_MASK | Mod::MOD1_MASK, TermMode::empty()), "\x1b[24;7~");
+        assert_eq!(escape(key::Delete, Mod::empty(), TermMode::empty()), "\x1b[3~");
+        assert_eq!(escape(key::Page_Up, Mod::empty(), TermMode::empty()), "\x1b[5~");
+        assert_eq!(escape(key::ISO_Left_Tab, Mod::SHIFT_MASK, TermMode::empty()), "\x1b[Z");
+    }
+
+    #[test]
+    fn character_keys_have_no_escape() {
+        assert_eq!(keyval_to_escape(key::a, Mod::empty(), TermMode::empty()), None);
+        // plain Tab is typed as a character
+        assert_eq!(keyval_to_escape(key::Tab, Mod::empty(), TermMode::empty()), None);
+    }
+
+    #[test]
+    fn multibyte_chars_are_utf8() {
+        assert_eq!(char_input('λ', false), vec![0xce, 0xbb]);
+        assert_eq!(char_input('你', false), vec![0xe4, 0xbd, 0xa0]);
+        // Ctrl only masks ASCII, never the bytes of a multibyte char
+        assert_eq!(char_input('你', true), vec![0xe4, 0xbd, 0xa0]);
+        assert_eq!(char_input('c', true), vec![0x03]);
+    }
+
+    #[test]
+    fn csi_u_encodes_ambiguous_keys() {
+        let encoded = |keyval, mods| String::from_utf8(csi_u(keyval, mods).expect("not encoded")).unwrap();
+        assert_eq!(encoded(key::Return, Mod::SHIFT_MASK), "\x1b[13;2u");
+        assert_eq!(encoded(key::Tab, Mod::CONTROL_MASK), "\x1b[9;5u");
+        // the unshifted key's codepoint
+        assert_eq!(encoded(key::I, Mod::CONTROL_MASK | Mod::SHIFT_MASK), "\x1b[105;6u");
+        // what the legacy encoding already tells apart stays legacy
+        assert_eq!(csi_u(key::Return, Mod::empty()), None);
+        assert_eq!(csi_u(key::A, Mod::SHIFT_MASK), None);
+        assert_eq!(csi_u(key::Up, Mod::CONTROL_MASK), None);
+    }
+
+    #[test]
+    fn keypad_follows_deckpam() {
+        assert_eq!(escape(key::KP_1, Mod::empty(), TermMode::empty()), "1");
+        assert_eq!(escape(key::KP_1, Mod::empty(), TermMode::APP_KEYPAD), "\x1bOq");
+        assert_eq!(escape(key::KP_Enter, Mod::empty(), TermMode::APP_KEYPAD), "\x1bOM");
+        assert_eq!(application_keypad(key::KP_Add, Mod::empty()), Some(b"\x1bOk".to_vec()));
+        // modified keypad keys are left to the IM and the usual handling
+        assert_eq!(application_keypad(key::KP_1, Mod::CONTROL_MASK), None);
+        assert_eq!(application_keypad(key::_1, Mod::empty()), None);
+    }
+}
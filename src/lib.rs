@@ -0,0 +1,26 @@
+//! Alacritty's terminal as a GTK 3 widget, see `widget::alacritty_widget_builder`.
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+pub mod util; // order matters for macros
+pub mod bindings;
+pub mod block;
+pub mod color;
+pub mod font;
+#[cfg(any(test, feature = "headless"))]
+pub mod headless;
+pub mod keys;
+pub mod modes;
+pub mod mouse;
+pub mod overlay;
+pub mod process;
+pub mod search;
+pub mod sequences;
+pub mod tap;
+pub mod text;
+pub mod url;
+pub mod widget;
+
+pub use crate::widget::{alacritty_widget, alacritty_widget_builder, GalacrittyBuilder, State};
@@ -3,8 +3,6 @@ use gio;
 use gdk;
 
 use alacritty;
-#[macro_use]
-extern crate log;
 
 use std::env::args;
 use std::rc::Rc;
@@ -14,10 +12,7 @@ use gio::prelude::*;
 use gio::{Menu, MenuExt, MenuItem, SimpleAction};
 use gtk::prelude::*;
 
-#[macro_use]
-pub mod util; // order matters for macros
-pub mod font;
-pub mod widget;
+use galacritty::{clone, font, widget};
 
 fn build_actions(app: gtk::Application,
                  window: gtk::ApplicationWindow,
@@ -38,63 +33,74 @@ fn build_actions(app: gtk::Application,
     }));
     app.add_action(&about_action);
 
+    // these are for the header bar buttons, the shortcuts are the widget's bindings
+    // (window accelerators would get the keys first, overriding the embedder's bindings)
+    let copy_action = SimpleAction::new("Copy", None);
+    copy_action.connect_activate(clone!(glarea, state => move |_, _| {
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            state.event_queue.push(widget::Event::Copy);
+        }
+        glarea.queue_draw();
+    }));
+    window.add_action(&copy_action);
+
     let paste_action = SimpleAction::new("Paste", None);
     paste_action.connect_activate(clone!(glarea, state => move |_, _| {
         if let Some(text) = clipboard.wait_for_text() {
-            let mut state = state.borrow_mut();
-            if let Some(ref mut state) = *state {
-                // TODO: bracketed paste support
-                state.event_queue.push(widget::Event::StringInput(text.replace("\r\n", "\r").replace("\n", "\r")));
-            }
-            glarea.queue_draw();
+            widget::paste(&state, &glarea, text);
         }
     }));
     window.add_action(&paste_action);
-    app.set_accels_for_action("win.Paste", &["<Control><Shift>v", "<Shift>Insert"]);
 
     let font_decr_action = SimpleAction::new("FontDecrease", None);
     font_decr_action.connect_activate(clone!(glarea, state => move |_, _| {
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
-            state.event_queue.push(widget::Event::ChangeFontSize(-1));
+            state.event_queue.push(widget::Event::ChangeFontSize(-1.0));
         }
         glarea.queue_draw();
     }));
     window.add_action(&font_decr_action);
-    app.set_accels_for_action("win.FontDecrease", &["<Control>minus", "<Control>KP_Subtract"]);
 
     let font_incr_action = SimpleAction::new("FontIncrease", None);
     font_incr_action.connect_activate(clone!(glarea, state => move |_, _| {
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
-            state.event_queue.push(widget::Event::ChangeFontSize(1));
+            state.event_queue.push(widget::Event::ChangeFontSize(1.0));
         }
         glarea.queue_draw();
     }));
     window.add_action(&font_incr_action);
-    app.set_accels_for_action("win.FontIncrease", &["<Control>equal", "<Control>plus", "<Control>KP_Add"]);
 
     let font_choose_action = SimpleAction::new("FontChoose", None);
     font_choose_action.connect_activate(clone!(glarea, window, state => move |_, _| {
         glarea.set_auto_render(false);
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
+        // the dialog runs a main loop of its own, with the widget's handlers (focus,
+        // blinking, output) still firing, so the state can't stay borrowed while it's open
+        let current = state.borrow().as_ref().map(|state| {
             let curf = state.config.font();
+            (curf.normal.family.clone(), curf.size)
+        });
+        if let Some((family, size)) = current {
             let dial = gtk::FontChooserDialog::new("Choose Terminal Font", Some(&window));
             dial.set_preview_text("if c0de$[1337] { \"hello\".world(); /* test */ }");
-            dial.set_font(&format!("{} {}", curf.normal.family, curf.size.as_f32_pts()));
+            dial.set_font(&format!("{} {}", family, size.as_f32_pts()));
             let acc : i32 = gtk::ResponseType::Ok.into();
-            if dial.run() == acc {
-                if let Some(fam) = dial.get_font_family() {
-                    let newf = font::to_alacritty(fam, dial.get_font_size());
-                    let fontdiff = (newf.size.as_f32_pts() - curf.size.as_f32_pts()) as i8;
-                    state.config.set_font(newf);
-                    state.event_queue.push(widget::Event::ChangeFontSize(fontdiff));
-                    // force reload the glyph cache if the size didn't change
-                    state.event_queue.push(widget::Event::HiDPIFactorChanged(glarea.get_scale_factor() as f32));
-                }
-            }
+            let chosen = if dial.run() == acc {
+                dial.get_font_family().map(|fam| font::to_alacritty(fam, dial.get_font_size()))
+            } else {
+                None
+            };
             dial.destroy();
+            let mut state = state.borrow_mut();
+            if let (Some(newf), Some(state)) = (chosen, state.as_mut()) {
+                let fontdiff = newf.size.as_f32_pts() - size.as_f32_pts();
+                state.config.set_font(newf);
+                state.event_queue.push(widget::Event::ChangeFontSize(fontdiff));
+                // force reload the glyph cache if the size didn't change
+                state.event_queue.push(widget::Event::HiDPIFactorChanged(glarea.get_scale_factor() as f32));
+            }
         }
         glarea.set_auto_render(true);
         glarea.queue_draw();
@@ -110,7 +116,6 @@ fn build_actions(app: gtk::Application,
         glarea.queue_draw();
     });
     window.add_action(&font_reset_action);
-    app.set_accels_for_action("win.FontReset", &["<Control>0", "<Control>KP_0"]);
 }
 
 fn build_main_menu() -> Menu {
@@ -151,6 +156,12 @@ fn build_header_bar() -> gtk::HeaderBar {
     paste_btn.set_action_name("win.Paste");
     header_bar.pack_end(&paste_btn);
 
+    let copy_btn = gtk::Button::new_from_icon_name("edit-copy-symbolic", gtk::IconSize::SmallToolbar.into());
+    copy_btn.set_can_focus(false);
+    copy_btn.set_tooltip_text("Copy to clipboard");
+    copy_btn.set_action_name("win.Copy");
+    header_bar.pack_end(&copy_btn);
+
     header_bar.set_show_close_button(true);
     header_bar
 }
@@ -179,7 +190,7 @@ fn build_ui(app: &gtk::Application) {
     build_actions(app.clone(), window.clone(), clipboard, glarea.clone(), state.clone());
 
     app.set_app_menu(Some(&build_main_menu()));
-    window.add(&glarea);
+    window.add(&widget::with_resize_overlay(&glarea, &state));
     window.show_all();
 }
 
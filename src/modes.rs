@@ -0,0 +1,51 @@
+use alacritty::term::TermMode;
+
+/// Which mouse events the program asked for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseTracking {
+    /// Presses and releases (`CSI ? 1000 h`)
+    Click,
+    /// Also motion while a button is held (`CSI ? 1002 h`)
+    Drag,
+    /// All motion (`CSI ? 1003 h`)
+    Motion,
+}
+
+/// The terminal modes (mostly DEC private ones) that affect input
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TerminalModes {
+    pub bracketed_paste: bool,
+    pub focus_events: bool,
+    pub mouse_tracking: Option<MouseTracking>,
+    /// Mouse reports use the SGR encoding (`CSI ? 1006 h`) instead of the legacy one
+    pub sgr_mouse: bool,
+    pub application_cursor: bool,
+    pub application_keypad: bool,
+    pub alternate_screen: bool,
+    /// The wheel sends arrow keys on the alternate screen (`CSI ? 1007 h`)
+    pub alternate_scroll: bool,
+}
+
+impl From<TermMode> for TerminalModes {
+    fn from(mode: TermMode) -> TerminalModes {
+        let mouse_tracking = if mode.contains(TermMode::MOUSE_MOTION) {
+            Some(MouseTracking::Motion)
+        } else if mode.contains(TermMode::MOUSE_DRAG) {
+            Some(MouseTracking::Drag)
+        } else if mode.contains(TermMode::MOUSE_REPORT_CLICK) {
+            Some(MouseTracking::Click)
+        } else {
+            None
+        };
+        TerminalModes {
+            bracketed_paste: mode.contains(TermMode::BRACKETED_PASTE),
+            focus_events: mode.contains(TermMode::FOCUS_IN_OUT),
+            mouse_tracking,
+            sgr_mouse: mode.contains(TermMode::SGR_MOUSE),
+            application_cursor: mode.contains(TermMode::APP_CURSOR),
+            application_keypad: mode.contains(TermMode::APP_KEYPAD),
+            alternate_screen: mode.contains(TermMode::ALT_SCREEN),
+            alternate_scroll: mode.contains(TermMode::ALTERNATE_SCROLL),
+        }
+    }
+}
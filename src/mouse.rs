@@ -0,0 +1,164 @@
+use std::cmp::min;
+use std::time::Duration;
+
+use gdk::ModifierType as Mod;
+
+use alacritty::index::{Point, Line, Column, Side};
+use alacritty::term::SizeInfo;
+use alacritty::term::cell::{Cell, Flags};
+
+use crate::modes::MouseTracking;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ButtonState {
+    Pressed,
+    Released,
+}
+
+/// Pointer state tracked across events
+#[derive(Default)]
+pub struct Mouse {
+    pub left_pressed: bool,
+    /// The cell the last motion report was for, motion within a cell isn't reported
+    pub last_motion_report: Option<Point>,
+    /// The cell the left button was last pressed on, for telling clicks from drags
+    pub press_point: Option<Point>,
+    last_click_time: u32,
+    click_count: u8,
+}
+
+impl Mouse {
+    /// Records a left click at the given event time (in ms), returning 1, 2 or 3
+    /// for single, double and triple clicks
+    pub fn register_click(&mut self, time: u32, timeout: Duration) -> u8 {
+        let timeout_ms = timeout.as_secs() as u32 * 1000 + timeout.subsec_millis();
+        if self.click_count > 0 && self.click_count < 3 && time.wrapping_sub(self.last_click_time) <= timeout_ms {
+            self.click_count += 1;
+        } else {
+            self.click_count = 1;
+        }
+        self.last_click_time = time;
+        self.click_count
+    }
+
+    /// Whether the last click was a single, double or triple one
+    pub fn click_count(&self) -> u8 {
+        self.click_count
+    }
+}
+
+/// Converts physical pixel coordinates to the grid cell under them, accounting for padding
+pub fn pixels_to_cell(size: &SizeInfo, x: f64, y: f64) -> Point {
+    let col = ((x - size.padding_x as f64) / size.cell_width as f64).max(0.0) as usize;
+    let line = ((y - size.padding_y as f64) / size.cell_height as f64).max(0.0) as usize;
+    Point::new(
+        Line(min(line, size.lines().0.saturating_sub(1))),
+        Column(min(col, size.cols().0.saturating_sub(1)))
+    )
+}
+
+/// Determines which half of the cell under the pointer it's on, for selection
+pub fn pixels_to_side(size: &SizeInfo, x: f64) -> Side {
+    let cell_x = (x - size.padding_x as f64).max(0.0) % size.cell_width as f64;
+    if cell_x > size.cell_width as f64 / 2.0 {
+        Side::Right
+    } else {
+        Side::Left
+    }
+}
+
+/// Makes a wide char (CJK, emoji) select as a whole: its two cells are the left and
+/// the right half of the glyph, whichever half of them the pointer is on
+pub fn wide_char_side(cell: &Cell, side: Side) -> Side {
+    if cell.flags.contains(Flags::WIDE_CHAR) {
+        Side::Left
+    } else if cell.flags.contains(Flags::WIDE_CHAR_SPACER) {
+        Side::Right
+    } else {
+        side
+    }
+}
+
+/// Maps a GDK button number to the xterm button code
+pub fn button_code(button: u32) -> Option<u8> {
+    match button {
+        1 => Some(0),
+        2 => Some(1),
+        3 => Some(2),
+        _ => None,
+    }
+}
+
+/// The code for reporting motion (32 plus the button held, 3 for none), `None` if the program
+/// doesn't want it: any motion is reported with 1003, only with a button held with 1002
+pub fn motion_code(tracking: Option<MouseTracking>, button: Option<u32>) -> Option<u8> {
+    let held = button.and_then(button_code);
+    match (tracking, held) {
+        (Some(MouseTracking::Motion), _) | (Some(MouseTracking::Drag), Some(_)) => Some(32 + held.unwrap_or(3)),
+        _ => None,
+    }
+}
+
+fn mods_code(mods: Mod) -> u8 {
+    let mut code = 0;
+    if mods.contains(Mod::SHIFT_MASK) {
+        code += 4;
+    }
+    if mods.intersects(Mod::MOD1_MASK | Mod::META_MASK) {
+        code += 8;
+    }
+    if mods.contains(Mod::CONTROL_MASK) {
+        code += 16;
+    }
+    code
+}
+
+/// Encodes a mouse report in the SGR (1006) or the legacy X10-style format
+pub fn report(code: u8, point: Point, mods: Mod, state: ButtonState, sgr: bool) -> Option<Vec<u8>> {
+    if sgr {
+        let c = if state == ButtonState::Released { 'm' } else { 'M' };
+        Some(format!("\x1b[<{};{};{}{}", code + mods_code(mods), point.col.0 + 1, point.line.0 + 1, c).into_bytes())
+    } else {
+        // the legacy encoding can't represent coordinates past 223
+        if point.line.0 >= 223 || point.col.0 >= 223 {
+            return None;
+        }
+        // ...nor which button was released
+        let code = if state == ButtonState::Released { 3 } else { code };
+        Some(vec![
+            0x1b, b'[', b'M',
+            32 + code + mods_code(mods),
+            32 + 1 + point.col.0 as u8,
+            32 + 1 + point.line.0 as u8,
+        ])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(line: usize, col: usize) -> Point {
+        Point::new(Line(line), Column(col))
+    }
+
+    #[test]
+    fn sgr_reports_are_one_based_and_tell_releases_apart() {
+        assert_eq!(report(0, point(0, 0), Mod::empty(), ButtonState::Pressed, true), Some(b"\x1b[<0;1;1M".to_vec()));
+        assert_eq!(report(2, point(4, 9), Mod::CONTROL_MASK, ButtonState::Released, true), Some(b"\x1b[<18;10;5m".to_vec()));
+    }
+
+    #[test]
+    fn x10_reports_are_offset_by_32() {
+        assert_eq!(report(0, point(0, 0), Mod::empty(), ButtonState::Pressed, false), Some(vec![0x1b, b'[', b'M', 32, 33, 33]));
+        // which button was released isn't encoded
+        assert_eq!(report(2, point(1, 2), Mod::SHIFT_MASK, ButtonState::Released, false), Some(vec![0x1b, b'[', b'M', 32 + 3 + 4, 35, 34]));
+    }
+
+    #[test]
+    fn coordinates_past_223_need_sgr() {
+        assert_eq!(report(0, point(0, 223), Mod::empty(), ButtonState::Pressed, false), None);
+        assert_eq!(report(0, point(300, 0), Mod::empty(), ButtonState::Pressed, false), None);
+        assert_eq!(report(0, point(300, 299), Mod::empty(), ButtonState::Pressed, true), Some(b"\x1b[<0;300;301M".to_vec()));
+    }
+}
@@ -0,0 +1,35 @@
+use alacritty::grid::Grid;
+use alacritty::index::Column;
+use alacritty::term::cell::Cell;
+
+/// Changes to cells that are only meant for one frame (hovered URLs, the block selection,
+/// downsampled colors). Alacritty draws straight from the grid, so they're made in it and
+/// undone right after drawing, all without letting go of the terminal's lock in between,
+/// or the program's output could land on (and be overwritten by) a changed cell.
+#[derive(Default)]
+pub struct Overlay {
+    /// The cells as they were before being changed (as buffer lines and columns),
+    /// in the order they were changed
+    saved: Vec<(usize, Column, Cell)>,
+}
+
+impl Overlay {
+    pub fn new() -> Overlay {
+        Overlay::default()
+    }
+
+    /// Changes a cell, remembering what it was
+    pub fn modify<F: FnOnce(&mut Cell)>(&mut self, grid: &mut Grid<Cell>, line: usize, col: Column, f: F) {
+        let cell = &mut grid[line][col];
+        self.saved.push((line, col, *cell));
+        f(cell);
+    }
+
+    /// Puts back every changed cell, the last change first so a cell changed
+    /// more than once ends up as it was before the first one
+    pub fn undo(self, grid: &mut Grid<Cell>) {
+        for (line, col, cell) in self.saved.into_iter().rev() {
+            grid[line][col] = cell;
+        }
+    }
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::io;
+use std::os::unix::io::RawFd;
+
+use libc;
+
+/// The process group in the foreground of the terminal, i.e. the program that's running
+/// in the shell (or the shell itself, at the prompt)
+pub fn foreground_pid(pty_fd: RawFd) -> Option<i32> {
+    let pgrp = unsafe { libc::tcgetpgrp(pty_fd) };
+    if pgrp > 0 { Some(pgrp) } else { None }
+}
+
+/// The name of a process, like `vim` or `ssh`.
+///
+/// Only works with a Linux-style `/proc`, `None` elsewhere.
+pub fn process_name(pid: i32) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    let name = comm.trim_end();
+    if name.is_empty() { None } else { Some(name.to_owned()) }
+}
+
+/// Sends a signal to a process group
+pub fn signal_group(pgid: i32, signal: i32) -> io::Result<()> {
+    if unsafe { libc::kill(-pgid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
@@ -0,0 +1,137 @@
+use regex::{self, Regex, RegexBuilder};
+
+use alacritty::grid::Scroll;
+use alacritty::index::{Point, Column, Side};
+use alacritty::selection::Selection;
+use alacritty::term::Term;
+
+use crate::text;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    /// Up, towards older output
+    Backward,
+    /// Down, towards newer output
+    Forward,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SearchOptions {
+    /// Treat the pattern as a regular expression instead of plain text
+    pub regex: bool,
+    pub case_insensitive: bool,
+}
+
+/// A match, as buffer lines (counted from the bottom, like `Selection` does) and columns
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub line: usize,
+    pub start: Column,
+    pub end: Column,
+}
+
+/// An ongoing search: all the matches, top to bottom, and which one is current
+pub struct Search {
+    pub matches: Vec<Match>,
+    pub current: usize,
+    direction: Direction,
+    /// The text of the current match's line when it was shown, see `is_stale`
+    current_text: String,
+}
+
+fn compile(pattern: &str, options: SearchOptions) -> Result<Regex, regex::Error> {
+    let pattern = if options.regex { pattern.to_owned() } else { regex::escape(pattern) };
+    RegexBuilder::new(&pattern).case_insensitive(options.case_insensitive).build()
+}
+
+/// Finds all the matches in the screen and scrollback, line by line
+fn find_matches(terminal: &Term, regex: &Regex) -> Vec<Match> {
+    let grid = terminal.grid();
+    let cols = grid.num_cols();
+    let mut matches = Vec::new();
+    for line in (0..grid.len()).rev() {
+        let (text, byte_cols) = text::row_text(&grid[line], cols);
+        for m in regex.find_iter(&text) {
+            if m.start() == m.end() {
+                continue;
+            }
+            matches.push(Match { line, start: Column(byte_cols[m.start()]), end: Column(byte_cols[m.end() - 1]) });
+        }
+    }
+    matches
+}
+
+impl Search {
+    /// Searches the terminal, starting from the bottom when going backward and from the top
+    /// when going forward. `None` means there are no matches.
+    pub fn new(terminal: &mut Term, pattern: &str, direction: Direction, options: SearchOptions) -> Result<Option<Search>, regex::Error> {
+        let matches = find_matches(terminal, &compile(pattern, options)?);
+        if matches.is_empty() {
+            return Ok(None);
+        }
+        let current = match direction {
+            Direction::Backward => matches.len() - 1,
+            Direction::Forward => 0,
+        };
+        let mut search = Search { matches, current, direction, current_text: String::new() };
+        search.show(terminal);
+        Ok(Some(search))
+    }
+
+    /// Moves to the next match in the search direction (or the opposite one), wrapping around
+    pub fn step(&mut self, terminal: &mut Term, reverse: bool) {
+        let forward = (self.direction == Direction::Forward) != reverse;
+        let len = self.matches.len();
+        self.current = if forward { (self.current + 1) % len } else { (self.current + len - 1) % len };
+        self.show(terminal);
+    }
+
+    /// Whether the matches may have moved since, which they do with the lines when output
+    /// scrolls them up. It's noticed by the current match's line not reading the same anymore.
+    pub fn is_stale(&self, terminal: &Term) -> bool {
+        let grid = terminal.grid();
+        let line = self.matches[self.current].line;
+        line >= grid.len() || text::row_text(&grid[line], grid.num_cols()).0 != self.current_text
+    }
+
+    /// Selects the current match (which is how it gets highlighted) and scrolls to it
+    fn show(&mut self, terminal: &mut Term) {
+        let m = self.matches[self.current];
+        // the history can have shrunk since the search
+        if m.line >= terminal.grid().len() {
+            return;
+        }
+        self.current_text = text::row_text(&terminal.grid()[m.line], terminal.grid().num_cols()).0;
+        let mut selection = Selection::simple(Point::new(m.line, m.start), Side::Left);
+        selection.update(Point::new(m.line, m.end), Side::Right);
+        *terminal.selection_mut() = Some(selection);
+
+        let screen_lines = terminal.grid().num_lines().0;
+        let offset = terminal.grid().display_offset();
+        if m.line < offset || m.line >= offset + screen_lines {
+            let target = m.line.saturating_sub(screen_lines / 2);
+            terminal.scroll_display(Scroll::Lines(target as isize - offset as isize));
+        }
+        terminal.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::headless::HeadlessTerminal;
+    use super::*;
+
+    #[test]
+    fn scrolling_output_makes_the_search_stale() {
+        let mut terminal = HeadlessTerminal::new(10, 2);
+        terminal.feed(b"foo\r\nbar");
+        let search = Search::new(terminal.term_mut(), "foo", Direction::Backward, SearchOptions::default())
+            .unwrap()
+            .unwrap();
+        // rewriting the same text in place moves nothing
+        terminal.feed(b"\x1b[Hfoo");
+        assert!(!search.is_stale(terminal.term()));
+        terminal.feed(b"\x1b[2;4H\r\nbaz");
+        assert!(search.is_stale(terminal.term()));
+    }
+}
@@ -0,0 +1,421 @@
+use std::str;
+
+/// What the program asked for with sequences Alacritty's parser drops
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// A new title, set with OSC 0 or 2 (which Alacritty handles too) or popped off
+    /// the title stack (`CSI 23 t`, which it doesn't)
+    Title(String),
+    /// OSC 52: put text on the clipboard, "CLIPBOARD" or "PRIMARY" (see `clipboard_reply`)
+    SetClipboard { selection: &'static str, text: String },
+    /// OSC 52 with `?` instead of the text: send back what's on the clipboard
+    QueryClipboard { selection: &'static str },
+    /// A desktop notification, from OSC 777 (`notify;summary;body`, like urxvt's)
+    /// or OSC 9 (just the body, like iTerm2's)
+    Notification { summary: String, body: String },
+    /// OSC 8: a link to `uri`, printed as `text` (once the link is closed)
+    Hyperlink { uri: String, text: String },
+}
+
+/// How many titles `CSI 22 t` keeps at most, like xterm
+const MAX_TITLE_STACK: usize = 10;
+
+/// Hyperlinks keep at most this many bytes of their text
+const MAX_LINK_TEXT: usize = 1024;
+
+/// Longer sequences are ignored, so that a broken one can't eat up all memory
+const MAX_SEQUENCE_LEN: usize = 1 << 20;
+
+/// Where the scanner is in the output
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ScanState {
+    Ground,
+    /// After an ESC
+    Escape,
+    /// After an ESC and its intermediate bytes (e.g. `ESC (` of a charset designation)
+    EscapeIntermediate,
+    /// In a CSI sequence (`ESC [`), until its final byte
+    Csi,
+    /// In an OSC string (`ESC ]`), until BEL or ST
+    Osc,
+    /// In a DCS, SOS, PM or APC string, until ST
+    String,
+}
+
+/// Follows the escape sequences in the program's output, roughly like Alacritty's parser does,
+/// for telling the text apart from everything else and picking up the `Request`s.
+///
+/// Output comes in chunks that can end anywhere, so this is fed one byte at a time.
+pub struct Scanner {
+    state: ScanState,
+    /// The parameters of the CSI sequence or the payload of the OSC string being read
+    buf: Vec<u8>,
+    /// Whether the sequence being read got too long for `buf`
+    overflowed: bool,
+    title: Option<String>,
+    /// The titles saved with `CSI 22 t`, `None` where there wasn't one
+    title_stack: Vec<Option<String>>,
+    /// The URI of the hyperlink that's open and the text printed since
+    link: Option<(String, Vec<u8>)>,
+}
+
+impl Scanner {
+    pub fn new() -> Scanner {
+        Scanner {
+            state: ScanState::Ground,
+            buf: Vec::new(),
+            overflowed: false,
+            title: None,
+            title_stack: Vec::new(),
+            link: None,
+        }
+    }
+
+    /// Scans the next byte of output, returning whether it's text: printable (including
+    /// UTF-8), a newline or a tab. Escape sequences and other control characters aren't.
+    ///
+    /// Sequences that ask for something are added to `requests` as they end.
+    pub fn advance(&mut self, byte: u8, requests: &mut Vec<Request>) -> bool {
+        // CAN and SUB cancel whatever sequence is going on
+        if byte == 0x18 || byte == 0x1a {
+            self.state = ScanState::Ground;
+            return false;
+        }
+        match self.state {
+            ScanState::Ground => match byte {
+                0x1b => self.state = ScanState::Escape,
+                b'\n' | b'\t' => return true,
+                0x00..=0x1f | 0x7f => (),
+                _ => {
+                    if let Some((_, ref mut text)) = self.link {
+                        if text.len() < MAX_LINK_TEXT {
+                            text.push(byte);
+                        }
+                    }
+                    return true;
+                },
+            },
+            ScanState::Escape | ScanState::EscapeIntermediate => {
+                self.state = match byte {
+                    0x1b => ScanState::Escape,
+                    b'[' if self.state == ScanState::Escape => ScanState::Csi,
+                    b']' if self.state == ScanState::Escape => ScanState::Osc,
+                    b'P' | b'X' | b'^' | b'_' if self.state == ScanState::Escape => ScanState::String,
+                    0x20..=0x2f => ScanState::EscapeIntermediate,
+                    // controls are carried out in the middle of sequences
+                    0x00..=0x1f => self.state,
+                    _ => ScanState::Ground,
+                };
+                if self.state == ScanState::Csi || self.state == ScanState::Osc {
+                    self.buf.clear();
+                    self.overflowed = false;
+                }
+            },
+            ScanState::Csi => match byte {
+                0x1b => self.state = ScanState::Escape,
+                0x40..=0x7e => {
+                    self.state = ScanState::Ground;
+                    self.csi_dispatch(byte, requests);
+                },
+                0x20..=0x3f => self.collect(byte),
+                _ => (),
+            },
+            ScanState::Osc => match byte {
+                // BEL, or the start of the ST (`ESC \`) that ends the string
+                0x07 | 0x1b => {
+                    self.state = if byte == 0x1b { ScanState::Escape } else { ScanState::Ground };
+                    self.osc_dispatch(requests);
+                },
+                _ => self.collect(byte),
+            },
+            ScanState::String => if byte == 0x1b {
+                self.state = ScanState::Escape;
+            },
+        }
+        false
+    }
+
+    fn collect(&mut self, byte: u8) {
+        if self.buf.len() < MAX_SEQUENCE_LEN {
+            self.buf.push(byte);
+        } else {
+            self.overflowed = true;
+        }
+    }
+
+    fn csi_dispatch(&mut self, action: u8, requests: &mut Vec<Request>) {
+        if action != b't' || self.overflowed {
+            return;
+        }
+        let params = match str::from_utf8(&self.buf) {
+            Ok(params) => params.split(';').map(|param| param.parse::<u32>().ok()).collect::<Vec<_>>(),
+            Err(_) => return,
+        };
+        // XTWINOPS: the second parameter is 0 for both the icon name and the title,
+        // 1 for the icon name only and 2 for the title only
+        let for_title = match params.get(1) {
+            None | Some(&Some(0)) | Some(&Some(2)) => true,
+            _ => false,
+        };
+        match params[0] {
+            Some(22) if for_title => {
+                if self.title_stack.len() == MAX_TITLE_STACK {
+                    self.title_stack.remove(0);
+                }
+                self.title_stack.push(self.title.clone());
+            },
+            Some(23) if for_title => {
+                // a title that wasn't set can't be put back, the last one stays
+                if let Some(Some(title)) = self.title_stack.pop() {
+                    self.set_title(title, requests);
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn osc_dispatch(&mut self, requests: &mut Vec<Request>) {
+        if self.overflowed {
+            return;
+        }
+        let payload = String::from_utf8_lossy(&self.buf).into_owned();
+        let (command, rest) = match payload.find(';') {
+            Some(i) => (&payload[..i], &payload[i + 1..]),
+            None => return,
+        };
+        match command {
+            "0" | "2" => self.set_title(rest.to_owned(), requests),
+            "8" => {
+                // the parameters (like `id=`) before the URI don't matter here
+                let uri = match rest.find(';') {
+                    Some(i) => &rest[i + 1..],
+                    None => return,
+                };
+                if let Some((uri, text)) = self.link.take() {
+                    let text = String::from_utf8_lossy(&text).trim().to_owned();
+                    if !text.is_empty() {
+                        requests.push(Request::Hyperlink { uri, text });
+                    }
+                }
+                // an empty URI closes the link
+                if !uri.is_empty() {
+                    self.link = Some((uri.to_owned(), Vec::new()));
+                }
+            },
+            "52" => clipboard_requests(rest, requests),
+            // ConEmu uses OSC 9 for other things too, those start with a number
+            "9" if !is_conemu_command(rest) => requests.push(Request::Notification { summary: String::new(), body: rest.to_owned() }),
+            "777" => {
+                let mut parts = rest.splitn(3, ';');
+                if parts.next() == Some("notify") {
+                    let summary = parts.next().unwrap_or_default().to_owned();
+                    let body = parts.next().unwrap_or_default().to_owned();
+                    requests.push(Request::Notification { summary, body });
+                }
+            },
+            _ => (),
+        }
+    }
+
+    fn set_title(&mut self, title: String, requests: &mut Vec<Request>) {
+        self.title = Some(title.clone());
+        requests.push(Request::Title(title));
+    }
+}
+
+/// Whether an OSC 9 payload is one of ConEmu's commands (e.g. `4;1;50` for progress)
+/// rather than a notification
+fn is_conemu_command(payload: &str) -> bool {
+    let command = payload.split(';').next().unwrap_or_default();
+    !command.is_empty() && command.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Parses OSC 52's `Pc;Pd`: which selections (`c` for the clipboard, `p` or `s` for the primary
+/// selection, the cut buffers aren't supported) and the base64 text or `?`
+fn clipboard_requests(payload: &str, requests: &mut Vec<Request>) {
+    let (targets, data) = match payload.find(';') {
+        Some(i) => (&payload[..i], &payload[i + 1..]),
+        None => return,
+    };
+    let mut selections = Vec::new();
+    for target in targets.chars() {
+        let selection = match target {
+            'c' => "CLIPBOARD",
+            'p' | 's' => "PRIMARY",
+            _ => continue,
+        };
+        if !selections.contains(&selection) {
+            selections.push(selection);
+        }
+    }
+    // xterm's default is the primary selection, but programs that leave it out
+    // (like tmux) mean the clipboard
+    if targets.is_empty() {
+        selections.push("CLIPBOARD");
+    }
+    if data == "?" {
+        // only one can be answered
+        if let Some(&selection) = selections.first() {
+            requests.push(Request::QueryClipboard { selection });
+        }
+    } else if let Some(text) = base64_decode(data.as_bytes()) {
+        let text = String::from_utf8_lossy(&text).into_owned();
+        requests.extend(selections.into_iter().map(|selection| Request::SetClipboard { selection, text: text.clone() }));
+    }
+}
+
+/// The answer to `Request::QueryClipboard`
+pub fn clipboard_reply(selection: &str, text: &str) -> Vec<u8> {
+    let target = if selection == "PRIMARY" { 'p' } else { 'c' };
+    format!("\x1b]52;{};{}\x07", target, base64_encode(text.as_bytes())).into_bytes()
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(bytes: &[u8]) -> String {
+    let mut encoded = String::with_capacity((bytes.len() + 2) / 3 * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (u32::from(b) << (16 - 8 * i)));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[((n >> (18 - 6 * i)) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// Decodes base64 (with or without the padding), `None` if it's not valid
+fn base64_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(encoded.len() / 4 * 3);
+    let (mut acc, mut bits) = (0u32, 0);
+    for &c in encoded {
+        if c == b'=' {
+            break;
+        }
+        let value = BASE64.iter().position(|&b| b == c)? as u32;
+        acc = (acc << 6) | value;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((acc >> bits) as u8);
+            acc &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+impl Default for Scanner {
+    fn default() -> Scanner {
+        Scanner::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{base64_decode, base64_encode, clipboard_reply, Request, Scanner};
+
+    fn text(output: &[u8]) -> String {
+        let mut scanner = Scanner::new();
+        let mut requests = Vec::new();
+        let text = output.iter().cloned().filter(|&b| scanner.advance(b, &mut requests)).collect();
+        String::from_utf8(text).unwrap()
+    }
+
+    fn requests(output: &[u8]) -> Vec<Request> {
+        let mut scanner = Scanner::new();
+        let mut requests = Vec::new();
+        for &byte in output {
+            scanner.advance(byte, &mut requests);
+        }
+        requests
+    }
+
+    fn title(title: &str) -> Request {
+        Request::Title(title.to_owned())
+    }
+
+    #[test]
+    fn escape_sequences_are_not_text() {
+        assert_eq!(text(b"\x1b[1;31mred\x1b[0m\r\n\x1b(Bplain\x1b=\tx"), "red\nplain\tx");
+        assert_eq!(text("\x1b]0;title ünï\x07é\x1b]2;other\x1b\\!".as_bytes()), "é!");
+        assert_eq!(text(b"\x1bP+q544e\x1b\\done"), "done");
+    }
+
+    #[test]
+    fn sequences_can_be_split_anywhere() {
+        let mut scanner = Scanner::new();
+        let mut text = Vec::new();
+        let mut requests = Vec::new();
+        for chunk in &[&b"a\x1b"[..], b"[3", b"8;5;1m", b"b\x1b]0;t", b"i\x07c"] {
+            text.extend(chunk.iter().cloned().filter(|&b| scanner.advance(b, &mut requests)));
+        }
+        assert_eq!(requests, vec![title("ti")]);
+        assert_eq!(text, b"abc".to_vec());
+    }
+
+    #[test]
+    fn cancelled_sequences_end() {
+        assert_eq!(text(b"\x1b[12\x18x\x1b]0;\x1ay"), "xy");
+    }
+
+    #[test]
+    fn popped_titles_come_back() {
+        assert_eq!(requests(b"\x1b]2;bash\x07\x1b[22;0t\x1b]2;vim\x1b\\\x1b[23;0t"), vec![title("bash"), title("vim"), title("bash")]);
+        // the bare and the title only variants too, but not the icon name only ones
+        assert_eq!(requests(b"\x1b]0;a\x07\x1b[22t\x1b]0;b\x07\x1b[23;2t"), vec![title("a"), title("b"), title("a")]);
+        assert_eq!(requests(b"\x1b]0;a\x07\x1b[22;1t\x1b]0;b\x07\x1b[23;1t"), vec![title("a"), title("b")]);
+    }
+
+    #[test]
+    fn nothing_is_popped_without_a_title() {
+        assert_eq!(requests(b"\x1b[23t\x1b[22t\x1b]2;vim\x07\x1b[23t"), vec![title("vim")]);
+    }
+
+    #[test]
+    fn base64_goes_both_ways() {
+        for &(text, encoded) in &[("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("ünï\n", "w7xuw68K")] {
+            assert_eq!(base64_encode(text.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded.as_bytes()), Some(text.as_bytes().to_vec()));
+        }
+        assert_eq!(base64_decode(b"Zm8"), Some(b"fo".to_vec()));
+        assert_eq!(base64_decode(b"Zm 8"), None);
+    }
+
+    #[test]
+    fn the_clipboard_can_be_set_and_queried() {
+        let set = |selection, text: &str| Request::SetClipboard { selection, text: text.to_owned() };
+        assert_eq!(requests(b"\x1b]52;c;aGVsbG8=\x07"), vec![set("CLIPBOARD", "hello")]);
+        assert_eq!(requests(b"\x1b]52;;aGVsbG8=\x1b\\"), vec![set("CLIPBOARD", "hello")]);
+        assert_eq!(requests(b"\x1b]52;ps0;aGk=\x07"), vec![set("PRIMARY", "hi")]);
+        assert_eq!(requests(b"\x1b]52;cp;aGk=\x07"), vec![set("CLIPBOARD", "hi"), set("PRIMARY", "hi")]);
+        assert_eq!(requests(b"\x1b]52;p;?\x07"), vec![Request::QueryClipboard { selection: "PRIMARY" }]);
+        // neither base64 nor a query
+        assert_eq!(requests(b"\x1b]52;c;not base64!\x07"), vec![]);
+        assert_eq!(clipboard_reply("CLIPBOARD", "hello"), b"\x1b]52;c;aGVsbG8=\x07".to_vec());
+    }
+
+    #[test]
+    fn notifications_have_a_summary_and_a_body() {
+        let notification = |summary: &str, body: &str| Request::Notification { summary: summary.to_owned(), body: body.to_owned() };
+        assert_eq!(requests(b"\x1b]777;notify;make;done; 0 errors\x07"), vec![notification("make", "done; 0 errors")]);
+        assert_eq!(requests(b"\x1b]777;notify;make\x07"), vec![notification("make", "")]);
+        assert_eq!(requests(b"\x1b]9;build finished\x1b\\"), vec![notification("", "build finished")]);
+        // neither are these
+        assert_eq!(requests(b"\x1b]777;preexec\x07\x1b]9;4;1;50\x07"), vec![]);
+    }
+
+    #[test]
+    fn hyperlinks_come_with_their_text() {
+        let link = |uri: &str, text: &str| Request::Hyperlink { uri: uri.to_owned(), text: text.to_owned() };
+        assert_eq!(requests(b"see \x1b]8;;https://example.com\x1b\\the \x1b[1mdocs\x1b[0m\x1b]8;;\x1b\\."),
+                   vec![link("https://example.com", "the docs")]);
+        // with parameters, and one link right after another
+        assert_eq!(requests(b"\x1b]8;id=1;file:///a\x07a\x1b]8;;file:///b\x07b\x1b]8;;\x07"),
+                   vec![link("file:///a", "a"), link("file:///b", "b")]);
+        // links without text are nowhere to be clicked
+        assert_eq!(requests(b"\x1b]8;;https://example.com\x07\x1b]8;;\x07"), vec![]);
+    }
+}
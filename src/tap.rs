@@ -0,0 +1,159 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use alacritty::tty::Pty;
+
+use crate::sequences::{Request, Scanner};
+
+/// What the widget shares with its IO thread's `TappedPty`
+#[derive(Default)]
+pub struct Tap {
+    /// Where the output goes while it's logged, see `start_logging`
+    log: Mutex<Option<mpsc::Sender<Vec<u8>>>>,
+    /// What the program asked for since the widget last looked
+    requests: Mutex<Vec<Request>>,
+}
+
+impl Tap {
+    pub fn new() -> Tap {
+        Tap::default()
+    }
+
+    /// Starts copying all of the output to a file (replacing it), as is or with the escape
+    /// sequences stripped, instead of wherever it went before.
+    ///
+    /// The file is written on a thread of its own, so a slow disk holds up neither
+    /// the IO thread nor rendering.
+    pub fn start_logging(&self, path: &Path, strip_escapes: bool) -> io::Result<()> {
+        let file = File::create(path)?;
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            if let Err(err) = write_log(rx, BufWriter::new(file), strip_escapes) {
+                error!("Could not write the log: {}", err);
+            }
+        });
+        // a log that was already going ends (and gets flushed) when its sender is dropped
+        *self.log.lock().unwrap() = Some(tx);
+        Ok(())
+    }
+
+    /// Stops logging, the output that was already read still gets written in the background
+    pub fn stop_logging(&self) {
+        *self.log.lock().unwrap() = None;
+    }
+
+    /// Takes the requests the program made since the last call, oldest first
+    pub fn take_requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().split_off(0)
+    }
+
+    /// Called on the IO thread with each chunk of output, before Alacritty parses it
+    fn output(&self, bytes: &[u8]) {
+        let mut log = self.log.lock().unwrap();
+        // the log's thread gives up after an error (and reports it)
+        if log.as_ref().map_or(false, |tx| tx.send(bytes.to_vec()).is_err()) {
+            *log = None;
+        }
+    }
+}
+
+/// Writes the output it receives until logging stops
+fn write_log<W: Write>(rx: mpsc::Receiver<Vec<u8>>, mut writer: W, strip_escapes: bool) -> io::Result<()> {
+    let mut scanner = Scanner::new();
+    let mut requests = Vec::new();
+    for bytes in rx {
+        if strip_escapes {
+            let text = bytes.into_iter().filter(|&b| scanner.advance(b, &mut requests)).collect::<Vec<_>>();
+            // the IO thread's scanner takes care of those
+            requests.clear();
+            writer.write_all(&text)?;
+        } else {
+            writer.write_all(&bytes)?;
+        }
+    }
+    writer.flush()
+}
+
+/// The PTY, giving the `Tap` a look at the program's output on its way to Alacritty.
+///
+/// The IO thread's `EventLoop` runs on this instead of the `Pty` itself: it only reads,
+/// writes and polls the fd, so the output is seen exactly once, in order, as it's read.
+pub struct TappedPty {
+    pty: Pty,
+    tap: Arc<Tap>,
+    scanner: Scanner,
+    requests: Vec<Request>,
+    /// Wakes up the widget for new requests
+    wake: Box<dyn Fn() + Send>,
+}
+
+impl TappedPty {
+    pub fn new(pty: Pty, tap: Arc<Tap>, wake: Box<dyn Fn() + Send>) -> TappedPty {
+        TappedPty { pty, tap, scanner: Scanner::new(), requests: Vec::new(), wake }
+    }
+}
+
+impl Read for TappedPty {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.pty.fd.read(buf)?;
+        if len > 0 {
+            self.tap.output(&buf[..len]);
+            for &byte in &buf[..len] {
+                self.scanner.advance(byte, &mut self.requests);
+            }
+            if !self.requests.is_empty() {
+                self.tap.requests.lock().unwrap().append(&mut self.requests);
+                (self.wake)();
+            }
+        }
+        Ok(len)
+    }
+}
+
+impl Write for TappedPty {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pty.fd.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.pty.fd.flush()
+    }
+}
+
+impl AsRawFd for TappedPty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.pty.fd.as_raw_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::write_log;
+
+    fn log(chunks: &[&[u8]], strip_escapes: bool) -> Vec<u8> {
+        let (tx, rx) = mpsc::channel();
+        for chunk in chunks {
+            tx.send(chunk.to_vec()).unwrap();
+        }
+        drop(tx);
+        let mut written = Vec::new();
+        write_log(rx, &mut written, strip_escapes).unwrap();
+        written
+    }
+
+    #[test]
+    fn the_log_has_the_raw_output() {
+        assert_eq!(log(&[b"\x1b[1mls\x1b", b"[0m\r\n"], false), b"\x1b[1mls\x1b[0m\r\n".to_vec());
+    }
+
+    #[test]
+    fn escapes_can_be_stripped_across_chunks() {
+        assert_eq!(log(&[b"\x1b[1mls\x1b", b"[0m\r\n", b"\x1b]0;t\x07ok"], true), b"ls\nok".to_vec());
+    }
+}
@@ -0,0 +1,76 @@
+use alacritty::grid::{Grid, Row};
+use alacritty::index::Column;
+use alacritty::term::cell::{Cell, Flags};
+
+/// Converts a grid row to text, also returning the column each byte of the text came from
+pub fn row_text(row: &Row<Cell>, cols: Column) -> (String, Vec<usize>) {
+    let mut text = String::with_capacity(cols.0);
+    let mut byte_cols = Vec::with_capacity(cols.0);
+    for col in 0..cols.0 {
+        let cell = &row[Column(col)];
+        // the second half of a wide char has nothing in it
+        if cell.flags.contains(Flags::WIDE_CHAR_SPACER) {
+            continue;
+        }
+        text.push(cell.c);
+        for _ in 0..cell.c.len_utf8() {
+            byte_cols.push(col);
+        }
+    }
+    (text, byte_cols)
+}
+
+/// Joins buffer lines (given top to bottom, counted from the bottom of the buffer)
+/// into newline separated text, optionally trimming trailing whitespace
+pub fn lines_text<I: Iterator<Item = usize>>(grid: &Grid<Cell>, lines: I, trim: bool) -> String {
+    let cols = grid.num_cols();
+    let rows = lines.map(|line| {
+        let (text, _) = row_text(&grid[line], cols);
+        if trim { text.trim_end().to_owned() } else { text }
+    });
+    rows.collect::<Vec<_>>().join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use alacritty::index::{Column, Side};
+    use alacritty::term::cell::Flags;
+
+    use crate::headless::HeadlessTerminal;
+    use crate::mouse;
+    use super::row_text;
+
+    #[test]
+    fn wide_chars_take_two_columns() {
+        let mut terminal = HeadlessTerminal::new(12, 1);
+        terminal.feed("日本語abc".as_bytes());
+        let row = &terminal.term().grid()[0];
+        for col in &[0, 2, 4] {
+            assert!(row[Column(*col)].flags.contains(Flags::WIDE_CHAR));
+            assert!(row[Column(col + 1)].flags.contains(Flags::WIDE_CHAR_SPACER));
+        }
+        // the ASCII after them lines up with the cells
+        assert_eq!(row[Column(6)].c, 'a');
+        assert_eq!(row[Column(8)].c, 'c');
+        assert_eq!(terminal.visible_text(), "日本語abc");
+    }
+
+    #[test]
+    fn spacers_are_skipped_in_text_but_not_in_columns() {
+        let mut terminal = HeadlessTerminal::new(12, 1);
+        terminal.feed("日本語abc".as_bytes());
+        let (text, byte_cols) = row_text(&terminal.term().grid()[0], Column(12));
+        assert!(text.starts_with("日本語abc"));
+        // three bytes per CJK char, all from the char's first cell
+        assert_eq!(&byte_cols[..10], &[0, 0, 0, 2, 2, 2, 4, 4, 4, 6]);
+    }
+
+    #[test]
+    fn wide_chars_select_as_a_whole() {
+        let mut terminal = HeadlessTerminal::new(12, 1);
+        terminal.feed("日".as_bytes());
+        let row = &terminal.term().grid()[0];
+        assert_eq!(mouse::wide_char_side(&row[Column(0)], Side::Right), Side::Left);
+        assert_eq!(mouse::wide_char_side(&row[Column(1)], Side::Left), Side::Right);
+    }
+}
@@ -0,0 +1,196 @@
+use regex::{self, Regex};
+
+use alacritty::grid::{Grid, Row};
+use alacritty::index::{Point, Line, Column};
+use alacritty::term::cell::{Cell, Flags};
+
+use crate::overlay::Overlay;
+use crate::text;
+
+/// The schemes recognized by default
+pub const DEFAULT_SCHEMES: &[&str] = &["http", "https", "file", "mailto"];
+
+/// A URL on the screen (URLs never span lines), as a visible line and inclusive columns
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Url {
+    pub line: Line,
+    pub start: Column,
+    pub end: Column,
+    pub url: String,
+}
+
+impl Url {
+    pub fn contains(&self, point: Point) -> bool {
+        point.line == self.line && point.col >= self.start && point.col <= self.end
+    }
+}
+
+/// A link printed with OSC 8, as its text and its target
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hyperlink {
+    pub uri: String,
+    pub text: String,
+}
+
+/// Finds URLs in the visible part of the grid
+pub struct UrlDetector {
+    regex: Regex,
+}
+
+impl UrlDetector {
+    /// Detects URLs with any of the given schemes, e.g. `&["https", "gemini"]`
+    pub fn new(schemes: &[&str]) -> UrlDetector {
+        let schemes = schemes.iter().map(|s| regex::escape(s)).collect::<Vec<_>>().join("|");
+        let regex = Regex::new(&format!(r#"\b(?:{}):[^\s<>"'`{{}}|\\^]+"#, schemes)).expect("URL regex");
+        UrlDetector { regex }
+    }
+
+    /// Detects whatever the regex matches, as is
+    pub fn with_regex(regex: Regex) -> UrlDetector {
+        UrlDetector { regex }
+    }
+
+    pub fn scan(&self, grid: &Grid<Cell>) -> Vec<Url> {
+        let cols = grid.num_cols();
+        let lines = grid.num_lines().0;
+        let offset = grid.display_offset();
+        let mut urls = Vec::new();
+        for line in 0..lines {
+            let row = &grid[offset + lines - 1 - line];
+            let (text, byte_cols) = text::row_text(row, cols);
+            for m in self.regex.find_iter(&text) {
+                let url = trim_url(m.as_str());
+                if url.is_empty() {
+                    continue;
+                }
+                let (start, end) = span(row, cols, &byte_cols, m.start(), m.start() + url.len());
+                urls.push(Url { line: Line(line), start, end, url: url.to_owned() });
+            }
+        }
+        urls
+    }
+}
+
+/// Finds the given hyperlinks in the visible part of the grid, with their URI as the URL.
+///
+/// The cells don't remember which link they were printed with, so links are found by their
+/// text instead: wherever it's on a line as a whole word, the earlier links winning.
+pub fn find_hyperlinks(grid: &Grid<Cell>, links: &[Hyperlink]) -> Vec<Url> {
+    let cols = grid.num_cols();
+    let lines = grid.num_lines().0;
+    let offset = grid.display_offset();
+    let mut urls = Vec::new();
+    for line in 0..lines {
+        let row = &grid[offset + lines - 1 - line];
+        let (text, byte_cols) = text::row_text(row, cols);
+        for link in links {
+            for (i, _) in text.match_indices(link.text.as_str()) {
+                let end = i + link.text.len();
+                let word_char = |c: Option<char>| c.map_or(false, char::is_alphanumeric);
+                if word_char(text[..i].chars().last()) || word_char(text[end..].chars().next()) {
+                    continue;
+                }
+                let (start, end) = span(row, cols, &byte_cols, i, end);
+                urls.push(Url { line: Line(line), start, end, url: link.uri.clone() });
+            }
+        }
+    }
+    urls
+}
+
+/// The columns of the bytes `start..end` of a row's text
+fn span(row: &Row<Cell>, cols: Column, byte_cols: &[usize], start: usize, end: usize) -> (Column, Column) {
+    let mut end_col = byte_cols[end - 1];
+    // a wide char at the end takes its spacer cell with it
+    if end_col + 1 < cols.0 && row[Column(end_col)].flags.contains(Flags::WIDE_CHAR) {
+        end_col += 1;
+    }
+    (Column(byte_cols[start]), Column(end_col))
+}
+
+impl Default for UrlDetector {
+    fn default() -> UrlDetector {
+        UrlDetector::new(DEFAULT_SCHEMES)
+    }
+}
+
+/// Drops trailing punctuation that's more likely part of the sentence than of the URL,
+/// keeping closing parens that have a matching opening one (like on Wikipedia)
+fn trim_url(url: &str) -> &str {
+    let mut url = url;
+    while let Some(c) = url.chars().last() {
+        let unbalanced_paren = c == ')' && url.matches('(').count() < url.matches(')').count();
+        if ".,:;!?".contains(c) || unbalanced_paren {
+            url = &url[..url.len() - 1];
+        } else {
+            break;
+        }
+    }
+    url
+}
+
+pub fn url_at(urls: &[Url], point: Point) -> Option<&Url> {
+    urls.iter().find(|url| url.contains(point))
+}
+
+/// Underlines a URL's cells for the frame
+pub fn underline(grid: &mut Grid<Cell>, url: &Url, overlay: &mut Overlay) {
+    let line = grid.display_offset() + grid.num_lines().0 - 1 - url.line.0;
+    for col in url.start.0..=url.end.0 {
+        overlay.modify(grid, line, Column(col), |cell| cell.flags.insert(Flags::UNDERLINE));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alacritty::index::{Column, Line, Point};
+
+    use crate::headless::HeadlessTerminal;
+    use super::*;
+
+    fn scan(output: &str) -> Vec<Url> {
+        let mut terminal = HeadlessTerminal::new(40, 3);
+        terminal.feed(output.as_bytes());
+        UrlDetector::default().scan(terminal.term().grid())
+    }
+
+    #[test]
+    fn urls_are_found_with_their_cells() {
+        let urls = scan("see https://example.com/a.\r\nmailto:me@example.com");
+        assert_eq!(urls, vec![
+            Url { line: Line(0), start: Column(4), end: Column(24), url: "https://example.com/a".to_owned() },
+            Url { line: Line(1), start: Column(0), end: Column(20), url: "mailto:me@example.com".to_owned() },
+        ]);
+        assert!(url_at(&urls, Point::new(Line(0), Column(10))).is_some());
+        assert!(url_at(&urls, Point::new(Line(0), Column(25))).is_none());
+    }
+
+    #[test]
+    fn only_the_given_schemes_are_urls() {
+        let mut terminal = HeadlessTerminal::new(40, 1);
+        terminal.feed(b"gopher://example.com");
+        assert!(UrlDetector::default().scan(terminal.term().grid()).is_empty());
+        assert_eq!(UrlDetector::new(&["gopher"]).scan(terminal.term().grid()).len(), 1);
+    }
+
+    #[test]
+    fn trailing_punctuation_is_trimmed_but_balanced_parens_stay() {
+        assert_eq!(trim_url("https://example.com/x),"), "https://example.com/x");
+        assert_eq!(trim_url("https://en.wikipedia.org/wiki/Rust_(language))"), "https://en.wikipedia.org/wiki/Rust_(language)");
+    }
+
+    #[test]
+    fn hyperlinks_are_found_by_their_text() {
+        let mut terminal = HeadlessTerminal::new(40, 2);
+        terminal.feed(b"\x1b]8;;file:///tmp/a\x07a\x1b]8;;\x07 la ab a\r\nnot a link");
+        let links = [Hyperlink { uri: "file:///tmp/a".to_owned(), text: "a".to_owned() }];
+        let ends = find_hyperlinks(terminal.term().grid(), &links).into_iter()
+            .map(|url| (url.line, url.start, url.end))
+            .collect::<Vec<_>>();
+        assert_eq!(ends, vec![
+            (Line(0), Column(0), Column(0)),
+            (Line(0), Column(8), Column(8)),
+            (Line(1), Column(4), Column(4)),
+        ]);
+    }
+}
@@ -1,4 +1,5 @@
 // from gtk examples
+#[macro_export]
 macro_rules! clone {
     (@param _) => ( _ );
     (@param $x:ident) => ( $x );
@@ -1,11 +1,22 @@
 use std::ptr;
-use std::rc::Rc;
+use std::mem;
+use std::rc::{Rc, Weak};
 use std::sync::Arc;
-use std::cell::RefCell;
-use std::thread::JoinHandle;
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use std::sync::mpsc;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::panic;
+use std::fs;
+use std::io;
+use std::thread::{self, JoinHandle};
 use std::os::unix::io::{RawFd, AsRawFd};
 
 use epoxy;
+use libc;
 use shared_library::dynamic_library::DynamicLibrary;
 
 use glib;
@@ -16,75 +27,2073 @@ use gtk::prelude::*;
 
 use alacritty::{cli, gl};
 use alacritty::display::{Display, DisplayCommand, InitialSize};
-use alacritty::event_loop::{self, EventLoop, WindowNotifier};
-use alacritty::tty::{self, Pty, process_should_exit};
+use alacritty::event_loop::{self, EventLoop, Msg, WindowNotifier};
+use alacritty::tty;
 use alacritty::sync::FairMutex;
-use alacritty::term::{Term, SizeInfo};
-use alacritty::config::Config;
+use alacritty::ansi::CursorStyle;
+use alacritty::grid::Scroll;
+use alacritty::term::{Term, TermMode, SizeInfo};
+use alacritty::term::cell::Flags;
+use alacritty::Rgb;
+use alacritty::config::{Config, Delta, Shell};
+use alacritty::selection::Selection;
+use alacritty::index::{Point, Line, Column, Side};
 
-// TODO vec for multiple widgets
+use crate::bindings::{self, Action, CopyPasteMode, KeyBinding};
+use crate::block::BlockSelection;
+use crate::color::{self, ColorMode, ColorScheme};
+use crate::font;
+use crate::keys;
+use crate::modes::TerminalModes;
+use crate::mouse::{self, ButtonState};
+use crate::overlay::Overlay;
+use crate::process;
+use crate::search::{Direction, Search, SearchOptions};
+use crate::sequences::{self, Request};
+use crate::tap::{Tap, TappedPty};
+use crate::text;
+use crate::url::{self, Hyperlink, Url, UrlDetector};
+
+// Widgets by id, for the notifiers (which live on the IO threads) to find them.
+// Weak, so that the notifiers don't keep destroyed widgets alive.
 thread_local!{
-    static GLOBAL: RefCell<Option<gtk::GLArea>> = RefCell::new(None);
+    static WIDGETS: RefCell<HashMap<usize, (glib::WeakRef<gtk::GLArea>, Weak<RefCell<Option<State>>>)>> = RefCell::new(HashMap::new());
+}
+
+static NEXT_WIDGET_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct IsControlHeld(bool);
+
+pub enum Event {
+    Blank,
+    CharInput(char, IsControlHeld),
+    StringInput(String),
+    RawBytes(Vec<u8>),
+    WindowResized(u32, u32),
+    HiDPIFactorChanged(f32),
+    /// By this many points, clamped to the sizes the renderer handles
+    ChangeFontSize(f32),
+    ResetFontSize,
+    MouseInput { button: u32, x: f64, y: f64, state: ButtonState, mods: Mod, time: u32 },
+    /// The pointer moved, with the given button (the lowest one, if any) held
+    MouseMotion { x: f64, y: f64, button: Option<u32>, mods: Mod },
+    /// Wheel notches (positive is up) at the given position, in physical pixels
+    Scroll { lines: i32, x: f64, y: f64, mods: Mod },
+    ScrollDisplay(Scroll),
+    Copy,
+    Paste(String),
+    SetFont(String),
+    SelectAll,
+    /// See `clear`
+    Clear,
+}
+
+impl Event {
+    /// Whether the event writes what the user typed (or pasted) to the PTY
+    fn is_input(&self) -> bool {
+        match *self {
+            Event::CharInput(..) | Event::StringInput(_) | Event::RawBytes(_) | Event::Paste(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn queue_draw(id: usize) {
+    if let Some(glarea) = widget(id) {
+        glarea.queue_draw();
+    }
+}
+
+/// Wakes up the widget when there's new output.
+///
+/// `draw_pending` is set from here until the main thread gets to it, so that a burst
+/// of output only schedules one wakeup instead of one per read.
+struct Notifier {
+    id: usize,
+    draw_pending: Arc<AtomicBool>,
+}
+
+impl WindowNotifier for Notifier {
+    fn notify(&self) {
+        if self.draw_pending.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        let id = self.id;
+        // NOTE: not gtk::idle_add, that one checks if we're on the main thread
+        let _ = glib::idle_add(move || {
+            output_arrived(id);
+            glib::Continue(false)
+        });
+    }
+}
+
+fn widget(id: usize) -> Option<gtk::GLArea> {
+    WIDGETS.with(|widgets| widgets.borrow().get(&id).and_then(|&(ref w, _)| w.upgrade()))
+}
+
+fn widget_state(id: usize) -> Option<Rc<RefCell<Option<State>>>> {
+    WIDGETS.with(|widgets| widgets.borrow().get(&id).and_then(|&(_, ref state)| state.upgrade()))
+}
+
+fn register_widget(id: usize, glarea: &gtk::GLArea, state: &Rc<RefCell<Option<State>>>) {
+    WIDGETS.with(|widgets| {
+        widgets.borrow_mut().insert(id, (glarea.downgrade(), Rc::downgrade(state)));
+    });
+}
+
+/// Handles new output (and the child exiting) on the main thread, whether or not the
+/// widget gets drawn: GTK doesn't render hidden widgets (e.g. background notebook pages),
+/// and those are the ones activity and silence are for
+fn output_arrived(id: usize) {
+    let (glarea, state_rc) = match (widget(id), widget_state(id)) {
+        (Some(glarea), Some(state_rc)) => (glarea, state_rc),
+        _ => return,
+    };
+    let mut activity_callback = None;
+    let mut title_callback = None;
+    let mut notifications = Vec::new();
+    let mut exited = None;
+    if let Some(ref mut state) = *state_rc.borrow_mut() {
+        // cleared first, so that output arriving from now on gets its own wakeup
+        if state.draw_pending.swap(false, Ordering::AcqRel) {
+            state.output_since_render = true;
+            state.content_changed(&state_rc);
+            if !state.focused {
+                activity_callback = state.output_in_background(&state_rc);
+            }
+            notifications = state.handle_requests();
+            if state.search.as_ref().map_or(false, |search| search.is_stale(&state.terminal.lock())) {
+                state.search = None;
+            }
+            // e.g. tab labels, which need updating while their page is hidden too
+            title_callback = state.update_title();
+        }
+        if state.child_hung_up.get() && !state.child_exited {
+            state.child_exited = true;
+            // render whatever the child printed last
+            state.terminal.lock().dirty = true;
+            exited = Some(state.on_child_exit.clone());
+        }
+    }
+    glarea.queue_draw();
+    // called without the state borrowed, so that they can use it
+    if let Some(on_activity) = activity_callback {
+        on_activity();
+    }
+    if let Some((on_title_change, title)) = title_callback {
+        on_title_change(&title);
+    }
+    for (on_notification, summary, body) in notifications {
+        on_notification(&summary, &body);
+    }
+    match exited {
+        Some(Some(on_child_exit)) => on_child_exit(None),
+        Some(None) => {
+            // the display's GL resources are freed on drop
+            glarea.make_current();
+            if let Some(state) = state_rc.replace(None) {
+                let _ = state.shutdown();
+            }
+            if let Some(window) = glarea.get_toplevel() {
+                window.destroy();
+            }
+        },
+        None => (),
+    }
+}
+
+fn unregister_widget(id: usize) {
+    WIDGETS.with(|widgets| {
+        widgets.borrow_mut().remove(&id);
+    });
+}
+
+pub struct State {
+    pub config: Config,
+    pub display: Display,
+    terminal: Arc<FairMutex<Term>>,
+    options: cli::Options,
+    env: HashMap<String, String>,
+    id: usize,
+    pty_fd: RawFd,
+    loop_notifier: event_loop::Notifier,
+    io_thread: Option<JoinHandle<(EventLoop<TappedPty>, event_loop::State)>>,
+    /// Shared with the IO thread, which shows it the output
+    tap: Arc<Tap>,
+    child_watch: Option<glib::SourceId>,
+    child_hung_up: Rc<Cell<bool>>,
+    draw_pending: Arc<AtomicBool>,
+    /// Whether there was output since the last render, for what only matters when drawing
+    output_since_render: bool,
+    /// Events waiting for `process_events`
+    pub event_queue: Vec<Event>,
+    scroll_accum: f64,
+    mouse: mouse::Mouse,
+    /// Maximum delay between clicks for them to count as a double or triple click
+    pub double_click_timeout: Duration,
+    /// Whether Alt+key sends ESC followed by the key, like xterm's metaSendsEscape
+    pub alt_sends_escape: bool,
+    /// Whether Backspace sends DEL (0x7f, the default) or BS (0x08, like ^H).
+    /// The Delete key always sends `ESC [ 3 ~`.
+    pub backspace_sends_delete: bool,
+    /// Encodes modified keys that are ambiguous otherwise (Ctrl+Shift+letter, Ctrl+Enter, ...)
+    /// as CSI u sequences (see `keys::csi_u`), off by default.
+    ///
+    /// Alacritty doesn't parse the sequences programs use to turn this on themselves,
+    /// so it's up to the embedder, e.g. for a terminal dedicated to neovim.
+    pub csi_u_keys: bool,
+    /// Jump back to the bottom when typing while scrolled back, on by default
+    pub scroll_on_keystroke: bool,
+    /// Jump back to the bottom when there's new output while scrolled back, off by default
+    pub scroll_on_output: bool,
+    /// Clicking (not dragging) on the line being edited moves the shell's cursor there,
+    /// by sending arrow keys. Off by default: it only makes sense at a readline-style prompt,
+    /// elsewhere the arrows go to whatever is running.
+    pub click_to_move_cursor: bool,
+    /// Dragging with these held selects a rectangle (e.g. a column of a table) instead of
+    /// flowing from line to line, Ctrl by default
+    pub block_selection_mods: Mod,
+    /// Only counts while there's no regular selection, which replaces it
+    block_selection: Option<BlockSelection>,
+    /// Ask before pasting text with newlines (or other control characters) from the clipboard,
+    /// unless the program uses bracketed paste (so the text can't run anything by itself)
+    pub confirm_multiline_paste: bool,
+    child_exited: bool,
+    /// Called once the shell (or whatever else was running) exits.
+    ///
+    /// The exit status is not available yet (Alacritty's SIGCHLD handler reaps the child),
+    /// so for now it's always `None`. When not set, the window is closed.
+    /// See `respawn` for restarting it instead.
+    pub on_child_exit: Option<Rc<dyn Fn(Option<i32>)>>,
+    /// Called with the new size (in points) after zooming in, out or back to normal.
+    pub on_font_size_change: Option<Rc<dyn Fn(f32)>>,
+    font_size_changed: bool,
+    /// How many points Ctrl+scroll zooms by per step
+    pub font_size_step: f32,
+    /// Shortcuts handled by the widget itself, checked before anything else
+    pub bindings: Vec<KeyBinding>,
+    /// Whether Ctrl+C and Ctrl+V (without Shift) copy and paste, off by default
+    pub copy_paste_mode: CopyPasteMode,
+    /// What programs can do with the clipboard (OSC 52), nothing by default
+    pub clipboard_access: ClipboardAccess,
+    im: gtk::IMMulticontext,
+    ime_enabled: bool,
+    search: Option<Search>,
+    /// Called when the program in the terminal sets the title.
+    ///
+    /// By default, this sets the title of the header bar passed when creating the widget,
+    /// or of the window without one.
+    pub on_title_change: Option<Rc<dyn Fn(&str)>>,
+    /// Where the title comes from
+    pub title_mode: TitleMode,
+    title: Option<String>,
+    /// The latest title the program set, as the tap saw it (see `update_title`)
+    output_title: Option<String>,
+    foreground_pid: Option<i32>,
+    /// See `set_bell_mode`
+    bell_mode: BellMode,
+    /// The config's own `visual_bell.duration`, which the bell mode overrides
+    visual_bell_duration: Duration,
+    last_bell: Option<Instant>,
+    /// Called when the terminal bell rings, regardless of the bell mode
+    pub on_bell: Option<Rc<dyn Fn()>>,
+    /// Whether programs can ask for desktop notifications (OSC 777 and OSC 9, e.g. when
+    /// a long build finishes), off by default
+    pub desktop_notifications: bool,
+    /// Called with the summary (empty for OSC 9) and the body of a desktop notification,
+    /// e.g. for showing a `gio::Notification`
+    pub on_notification: Option<Rc<dyn Fn(&str, &str)>>,
+    /// Whether the cursor blinks while the widget has focus.
+    ///
+    /// Turning it off takes effect at the next blink, turning it on at the next key press
+    /// or focus change.
+    pub cursor_blink: bool,
+    cursor_visible: bool,
+    blink_timer: Option<glib::SourceId>,
+    /// Whether the widget has keyboard focus, the cursor is drawn hollow otherwise
+    focused: bool,
+    /// Finds the URLs that can be Ctrl+clicked, `None` turns that off (links printed
+    /// with OSC 8 can be clicked either way)
+    pub url_detector: Option<UrlDetector>,
+    /// The OSC 8 links printed lately, newest first
+    hyperlinks: Vec<Hyperlink>,
+    urls: Vec<Url>,
+    /// The cell under the pointer, if it's over the widget and no button is held
+    hover_point: Option<Point>,
+    /// The cell and the URL a Ctrl+click was pressed on, which opens on release
+    /// unless the pointer was dragged off the cell in between
+    url_press: Option<(Point, String)>,
+    /// Shown on right click. Embedders can append their own items to it.
+    pub context_menu: gtk::Menu,
+    /// The menu's Copy and Paste, which are only enabled when there's something to copy
+    /// or paste, wherever embedders move them
+    copy_item: gtk::MenuItem,
+    paste_item: gtk::MenuItem,
+    resize_timer: Option<glib::SourceId>,
+    /// The latest size while resizing, not applied yet
+    pending_size: Option<(u32, u32)>,
+    /// Called with the new (columns, rows) when the size of the grid changes
+    pub on_resize: Option<Rc<dyn Fn(usize, usize)>>,
+    /// Shows the new size, see `with_resize_overlay`, called before `on_resize`
+    show_size: Option<Rc<dyn Fn(usize, usize)>>,
+    grid_size: (usize, usize),
+    /// Called with the new `scroll_position` when it changes, e.g. for showing a scrollbar
+    /// (or a fading indicator) while scrolled back
+    pub on_scroll: Option<Rc<dyn Fn(usize, usize)>>,
+    scroll_position: (usize, usize),
+    /// Limits how often the terminal is redrawn, `None` redraws on every frame with changes.
+    ///
+    /// GTK already draws at most once per frame of the display (vblank), this is for going
+    /// below that, e.g. to save battery during lots of output.
+    pub max_fps: Option<u32>,
+    last_frame: Option<Instant>,
+    frame_scheduled: Rc<Cell<bool>>,
+    /// Limits the colors shown, 24-bit by default
+    pub color_mode: ColorMode,
+    /// Set by `set_cursor_color` or a scheme's cursor color, whichever came last
+    cursor_color: CursorColor,
+    /// Called when there's output while the widget is unfocused (e.g. for marking its tab),
+    /// once until it's focused again
+    pub on_activity: Option<Rc<dyn Fn()>>,
+    activity_reported: bool,
+    /// Called when there's been no output for `silence_timeout` after some output
+    /// while the widget is unfocused
+    pub on_silence: Option<Rc<dyn Fn()>>,
+    pub silence_timeout: Duration,
+    silence_timer: Option<glib::SourceId>,
+    /// Overrides the config's `scrolling.history`, see `set_scrollback_limit`
+    scrollback_limit: Option<usize>,
+    /// Overrides the config's `window.padding`, see `set_padding`
+    padding: Option<Delta<u8>>,
+    /// Overrides the config's `selection.semantic_escape_chars`, see `set_word_separators`
+    word_separators: Option<String>,
+    /// Overrides the config's `background_opacity`, see `set_background_opacity`
+    background_opacity: Option<f32>,
+    /// Called (at most every `CONTENT_CHANGE_DEBOUNCE_MS`) when the program has printed
+    /// something, e.g. for a screen reader to read `visible_text` again.
+    ///
+    /// Alacritty doesn't track which lines changed, so this doesn't say what did.
+    pub on_content_change: Option<Rc<dyn Fn()>>,
+    content_change_timer: Option<glib::SourceId>,
+    /// Keeps scrolling while a selection is dragged past the top or the bottom
+    autoscroll_timer: Option<glib::SourceId>,
+    /// Where the pointer was last dragged to, in physical pixels
+    drag_position: (f64, f64),
+}
+
+/// What the title (see `on_title_change`) is
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TitleMode {
+    /// The name of the program running in the foreground (e.g. `vim`), whenever that changes.
+    /// In between, programs can still set the title.
+    ///
+    /// Without a Linux-style `/proc`, this works like `EscapeSequence`.
+    ProcessName,
+    /// Whatever programs set with escape sequences, like most terminals do
+    EscapeSequence,
+    /// Always the same
+    Static(String),
+}
+
+/// How the cursor is colored
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CursorColor {
+    /// Always the same color, with the text under it in black or white (whichever is legible)
+    Fixed(Rgb),
+    /// Swaps the foreground and background of the cell under it, so it's visible on anything
+    Reverse,
+}
+
+/// What programs can do with the clipboard and the primary selection through OSC 52,
+/// e.g. vim or tmux copying from a remote machine over SSH
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClipboardAccess {
+    /// Nothing: whatever runs in the terminal could put a command there for the user
+    /// to paste, or read what was copied elsewhere (like passwords)
+    None,
+    /// Copying only
+    Write,
+    /// Copying, and reading what's there
+    ReadWrite,
+}
+
+/// What happens when a program rings the bell (`\a`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BellMode {
+    /// Beeps using the display's bell
+    Audible,
+    /// Flashes the terminal, using the `visual_bell` settings from the config
+    Visual,
+    Both,
+    None,
+}
+
+impl BellMode {
+    fn is_audible(self) -> bool {
+        self == BellMode::Audible || self == BellMode::Both
+    }
+
+    fn is_visual(self) -> bool {
+        self == BellMode::Visual || self == BellMode::Both
+    }
+}
+
+/// How long the visual bell flashes when the config's duration is 0
+const DEFAULT_VISUAL_BELL_DURATION: Duration = Duration::from_millis(150);
+
+/// The visual bell duration to give Alacritty for the mode: it flashes for the config's
+/// whenever the bell rings, so that's 0 unless the mode flashes
+fn bell_duration(mode: BellMode, configured: Duration) -> Duration {
+    if !mode.is_visual() {
+        Duration::from_millis(0)
+    } else if configured == Duration::from_millis(0) {
+        DEFAULT_VISUAL_BELL_DURATION
+    } else {
+        configured
+    }
+}
+
+/// Bells closer together than this are ignored, so that a flood of them doesn't hang the UI
+const BELL_DEBOUNCE: Duration = Duration::from_millis(100);
+
+/// Zoom limits, in points
+const MIN_FONT_SIZE: f32 = 4.0;
+const MAX_FONT_SIZE: f32 = 200.0;
+
+/// What the child sees as `TERM`, matching the escape sequences Alacritty implements
+/// (and a terminfo entry that's installed pretty much everywhere)
+const TERM: &str = "xterm-256color";
+
+/// How long resizing has to stop for before the final size is applied
+const RESIZE_DEBOUNCE_MS: u32 = 100;
+
+/// The smallest grid the widget asks GTK for, in columns and rows
+const MIN_GRID_SIZE: (usize, usize) = (20, 4);
+
+/// How often `on_content_change` is called at most during output
+const CONTENT_CHANGE_DEBOUNCE_MS: u32 = 200;
+
+/// How often the view scrolls by a line while a selection is dragged past its edges
+const AUTOSCROLL_INTERVAL_MS: u32 = 50;
+
+/// How long to wait for the IO thread to stop when tearing down a terminal
+const IO_THREAD_JOIN_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How many OSC 8 links are looked for on the screen
+const MAX_HYPERLINKS: usize = 100;
+
+impl State {
+    /// Stops the IO thread and waits for it (for a little while), closing the PTY.
+    ///
+    /// The child gets a SIGHUP first, like when a terminal window is closed, so that
+    /// the shell ends its jobs too.
+    fn stop_child(&mut self) {
+        let _ = signal_child(self, libc::SIGHUP);
+        if let Some(child_watch) = self.child_watch.take() {
+            // the watch removes itself after firing
+            if !self.child_hung_up.get() {
+                glib::source_remove(child_watch);
+            }
+        }
+        if let Some(io_thread) = self.io_thread.take() {
+            stop_io_thread(&self.loop_notifier, io_thread);
+        }
+    }
+
+    /// Beeps if needed, returning the callback to call (unless the bell was debounced)
+    fn ring_bell(&mut self) -> Option<Rc<dyn Fn()>> {
+        let now = Instant::now();
+        if self.last_bell.map_or(false, |last| now.duration_since(last) < BELL_DEBOUNCE) {
+            return None;
+        }
+        self.last_bell = Some(now);
+        if self.bell_mode.is_audible() {
+            if let Some(display) = gdk::Display::get_default() {
+                display.beep();
+            }
+        }
+        self.on_bell.clone()
+    }
+
+    fn stop_blinking(&mut self) {
+        if let Some(blink_timer) = self.blink_timer.take() {
+            glib::source_remove(blink_timer);
+        }
+        if !self.cursor_visible {
+            self.cursor_visible = true;
+            self.terminal.lock().dirty = true;
+        }
+    }
+
+    /// Whether drawing now would go over `max_fps`, in which case a redraw is scheduled
+    /// for when the next frame is due (the terminal stays dirty until then)
+    fn frame_capped(&self) -> bool {
+        let (max_fps, last_frame) = match (self.max_fps, self.last_frame) {
+            (Some(max_fps), Some(last_frame)) if max_fps > 0 => (max_fps, last_frame),
+            _ => return false,
+        };
+        let frame_time = Duration::from_secs(1) / max_fps;
+        let elapsed = last_frame.elapsed();
+        if elapsed >= frame_time {
+            return false;
+        }
+        if !self.frame_scheduled.replace(true) {
+            let remaining = frame_time - elapsed;
+            let ms = remaining.as_secs() as u32 * 1000 + remaining.subsec_millis() + 1;
+            let (id, frame_scheduled) = (self.id, self.frame_scheduled.clone());
+            gtk::timeout_add(ms, move || {
+                frame_scheduled.set(false);
+                queue_draw(id);
+                glib::Continue(false)
+            });
+        }
+        true
+    }
+
+    /// Restarts the silence timer, returning the activity callback if it's the first output
+    fn output_in_background(&mut self, state_rc: &Rc<RefCell<Option<State>>>) -> Option<Rc<dyn Fn()>> {
+        if let Some(silence_timer) = self.silence_timer.take() {
+            glib::source_remove(silence_timer);
+        }
+        if self.on_silence.is_some() {
+            let timeout = self.silence_timeout;
+            let ms = timeout.as_secs() as u32 * 1000 + timeout.subsec_millis();
+            self.silence_timer = Some(gtk::timeout_add(ms, clone!(state_rc => move || {
+                let on_silence = state_rc.borrow_mut().as_mut().and_then(|state| {
+                    state.silence_timer = None;
+                    state.on_silence.clone()
+                });
+                if let Some(on_silence) = on_silence {
+                    on_silence();
+                }
+                glib::Continue(false)
+            })));
+        }
+        if self.activity_reported {
+            return None;
+        }
+        self.activity_reported = true;
+        self.on_activity.clone()
+    }
+
+    /// Handles what the program asked for (see `Request`) since the last call,
+    /// returning the notifications to call `on_notification` with
+    fn handle_requests(&mut self) -> Vec<(Rc<dyn Fn(&str, &str)>, String, String)> {
+        let mut notifications = Vec::new();
+        for request in self.tap.take_requests() {
+            match request {
+                Request::Title(title) => self.output_title = Some(title),
+                Request::SetClipboard { selection, text } => if self.clipboard_access != ClipboardAccess::None {
+                    gtk::Clipboard::get(&gdk::Atom::intern(selection)).set_text(&text);
+                },
+                Request::QueryClipboard { selection } => if self.clipboard_access == ClipboardAccess::ReadWrite {
+                    let id = self.id;
+                    gtk::Clipboard::get(&gdk::Atom::intern(selection)).request_text(move |_, text| {
+                        let text = text.map(|text| text.to_string()).unwrap_or_default();
+                        if let Some(state) = widget_state(id) {
+                            if let Some(ref mut state) = *state.borrow_mut() {
+                                send_bytes(state, &sequences::clipboard_reply(selection, &text));
+                            }
+                        }
+                    });
+                },
+                Request::Hyperlink { uri, text } => {
+                    let link = Hyperlink { uri, text };
+                    self.hyperlinks.retain(|old| *old != link);
+                    self.hyperlinks.insert(0, link);
+                    self.hyperlinks.truncate(MAX_HYPERLINKS);
+                },
+                Request::Notification { summary, body } => if self.desktop_notifications {
+                    if let Some(ref on_notification) = self.on_notification {
+                        notifications.push((on_notification.clone(), summary, body));
+                    }
+                },
+            }
+        }
+        notifications
+    }
+
+    /// Picks up title changes, returning the callback to call with the new title
+    fn update_title(&mut self) -> Option<(Rc<dyn Fn(&str)>, String)> {
+        let mut terminal = self.terminal.lock();
+        // the tap sees every title Alacritty does, in the same order, and also the ones
+        // popped off the title stack (which Alacritty doesn't keep), so its title wins
+        let title = match self.title_mode {
+            TitleMode::EscapeSequence => self.output_title.take().or(terminal.get_next_title()),
+            TitleMode::Static(ref title) => Some(title.clone()),
+            TitleMode::ProcessName => {
+                let title = self.output_title.take().or(terminal.get_next_title());
+                let pid = process::foreground_pid(self.pty_fd);
+                if pid != self.foreground_pid {
+                    self.foreground_pid = pid;
+                    pid.and_then(process::process_name).or(title)
+                } else {
+                    title
+                }
+            },
+        };
+        drop(terminal); // mutex unlock
+        let title = title.filter(|title| self.title.as_ref() != Some(title))?;
+        self.title = Some(title.clone());
+        self.on_title_change.clone().map(|cb| (cb, title))
+    }
+
+    /// Schedules `on_content_change`, unless it's already coming
+    fn content_changed(&mut self, state_rc: &Rc<RefCell<Option<State>>>) {
+        if self.on_content_change.is_none() || self.content_change_timer.is_some() {
+            return;
+        }
+        self.content_change_timer = Some(gtk::timeout_add(CONTENT_CHANGE_DEBOUNCE_MS, clone!(state_rc => move || {
+            let on_content_change = state_rc.borrow_mut().as_mut().and_then(|state| {
+                state.content_change_timer = None;
+                state.on_content_change.clone()
+            });
+            if let Some(on_content_change) = on_content_change {
+                on_content_change();
+            }
+            glib::Continue(false)
+        })));
+    }
+
+    /// Adds a shortcut, taking precedence over the existing ones
+    pub fn add_binding(&mut self, binding: KeyBinding) {
+        self.bindings.insert(0, binding);
+    }
+
+    /// Removes all shortcuts, including the default ones
+    pub fn clear_bindings(&mut self) {
+        self.bindings.clear();
+    }
+
+    /// Stops everything, handing back the config (for the next realize)
+    fn shutdown(mut self) -> Config {
+        self.stop_blinking();
+        if let Some(silence_timer) = self.silence_timer.take() {
+            glib::source_remove(silence_timer);
+        }
+        if let Some(resize_timer) = self.resize_timer.take() {
+            glib::source_remove(resize_timer);
+        }
+        if let Some(autoscroll_timer) = self.autoscroll_timer.take() {
+            glib::source_remove(autoscroll_timer);
+        }
+        if let Some(content_change_timer) = self.content_change_timer.take() {
+            glib::source_remove(content_change_timer);
+        }
+        self.stop_child();
+        self.config
+    }
+}
+
+/// Waits for a thread to finish for up to `timeout`, returning whether it finished
+/// without panicking, or `None` if it's still running
+fn join_timeout<T: Send + 'static>(thread: JoinHandle<T>, timeout: Duration) -> Option<bool> {
+    // JoinHandle::join can't time out, so join on a helper thread instead
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // whatever the thread returned (like the PTY) is dropped before we say it's done
+        let finished = thread.join().is_ok();
+        let _ = tx.send(finished);
+    });
+    rx.recv_timeout(timeout).ok()
+}
+
+/// The variables the child gets on top of our environment: `TERM` and `COLORTERM`,
+/// then the config's `env`, then the widget's (see `GalacrittyBuilder::env`)
+fn child_env(config_env: &HashMap<String, String>, env: &HashMap<String, String>) -> HashMap<String, String> {
+    let mut vars = HashMap::new();
+    // whatever the parent had might not match what we support
+    vars.insert("TERM".to_owned(), TERM.to_owned());
+    // the renderer does 24-bit color
+    vars.insert("COLORTERM".to_owned(), "truecolor".to_owned());
+    vars.extend(config_env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    vars.extend(env.iter().map(|(k, v)| (k.clone(), v.clone())));
+    vars
+}
+
+/// Spawns the child process in a new PTY, and the IO thread for it.
+///
+/// Nothing notices the child exiting until the PTY is watched with `watch_child`.
+fn spawn_child(id: usize, config: &mut Config, options: &cli::Options, env: &HashMap<String, String>,
+               terminal: &Arc<FairMutex<Term>>, size: &SizeInfo, tap: &Arc<Tap>, draw_pending: &Arc<AtomicBool>)
+               -> (RawFd, event_loop::Notifier, JoinHandle<(EventLoop<TappedPty>, event_loop::State)>) {
+    // NOTE: Alacritty's tty::setup_env puts these in our own environment, where other threads
+    // may be reading it at the same time. tty::new sets the config's env on the child's
+    // command only, so they go there instead.
+    let vars = child_env(config.env(), env);
+    config.set_env(vars);
+    let wake = Notifier { id, draw_pending: Arc::clone(draw_pending) };
+    let pty = TappedPty::new(tty::new(config, options, size, None), Arc::clone(tap), Box::new(move || wake.notify()));
+    let pty_fd = pty.as_raw_fd();
+
+    let event_loop = EventLoop::new(
+        Arc::clone(terminal),
+        Box::new(Notifier { id, draw_pending: Arc::clone(draw_pending) }),
+        pty,
+        options.ref_test,
+    );
+
+    let loop_notifier = event_loop::Notifier(event_loop.channel());
+    let io_thread = event_loop.spawn(None);
+    (pty_fd, loop_notifier, io_thread)
+}
+
+/// Sets `hung_up` and handles the exit when the child's side of the PTY is closed
+fn watch_child(id: usize, pty_fd: RawFd, hung_up: Rc<Cell<bool>>) -> glib::SourceId {
+    // NOTE: Alacritty's own tty::process_should_exit is global (only tracks the last child)
+    // and can't be reset, so watch for HUP on our PTY instead
+    glib::unix_fd_add_local(pty_fd, glib::IOCondition::HUP, move |_, _| {
+        hung_up.set(true);
+        output_arrived(id);
+        glib::Continue(false)
+    })
+}
+
+/// Tells the IO thread to stop and waits for it, which closes the PTY.
+///
+/// NOTE: an IO thread that doesn't stop in time is left behind, and the PTY's fd stays open
+/// with it until it stops (if ever). The fd isn't closed here: the thread could still be using it,
+/// and by then the number could belong to another file.
+fn stop_io_thread(loop_notifier: &event_loop::Notifier, io_thread: JoinHandle<(EventLoop<TappedPty>, event_loop::State)>) {
+    let _ = loop_notifier.0.send(Msg::Shutdown);
+    match join_timeout(io_thread, IO_THREAD_JOIN_TIMEOUT) {
+        Some(true) => (),
+        Some(false) => warn!("The IO thread panicked"),
+        None => warn!("The IO thread did not stop in time, leaving it and the PTY behind"),
+    }
+}
+
+/// Replaces the child process (dead or alive) with a fresh one, started the same way.
+///
+/// The old IO thread is stopped first. With `clear`, the terminal contents are reset,
+/// otherwise the new process starts wherever the old one left off.
+pub fn respawn(state: &mut State, clear: bool) {
+    state.stop_child();
+    if clear {
+        use alacritty::ansi::Handler;
+        let mut terminal = state.terminal.lock();
+        terminal.reset_state();
+        terminal.dirty = true;
+        state.hyperlinks.clear();
+        state.search = None;
+    }
+    state.child_hung_up = Rc::new(Cell::new(false));
+    state.child_exited = false;
+    let (pty_fd, loop_notifier, io_thread) = spawn_child(
+        state.id, &mut state.config, &state.options, &state.env, &state.terminal,
+        state.display.size(), &state.tap, &state.draw_pending);
+    state.pty_fd = pty_fd;
+    state.loop_notifier = loop_notifier;
+    state.io_thread = Some(io_thread);
+    state.child_watch = Some(watch_child(state.id, pty_fd, state.child_hung_up.clone()));
+}
+
+/// The size of the terminal in cells, as (columns, rows).
+pub fn grid_size(state: &State) -> (usize, usize) {
+    let size = state.display.size();
+    (size.cols().0, size.lines().0)
+}
+
+/// Where the view is in the history, as (lines scrolled back from the bottom, lines in total),
+/// with the screen's lines counted in the total.
+pub fn scroll_position(state: &State) -> (usize, usize) {
+    terminal_scroll_position(&state.terminal.lock())
+}
+
+fn terminal_scroll_position(terminal: &Term) -> (usize, usize) {
+    let grid = terminal.grid();
+    (grid.display_offset(), grid.len())
+}
+
+/// Resizes the window so that the terminal is `cols` by `rows` cells (at least 1x1),
+/// e.g. for resetting it to 80x24.
+///
+/// The grid and the PTY follow once GTK gives the widget its new size, which is up to
+/// the window manager (e.g. a tiling one may not allow it).
+pub fn resize_cells(state: &mut State, cols: usize, rows: usize) {
+    let glarea = match widget(state.id) {
+        Some(glarea) => glarea,
+        None => return,
+    };
+    let window = match glarea.get_toplevel().and_then(|w| w.downcast::<gtk::Window>().ok()) {
+        Some(window) => window,
+        None => return,
+    };
+    let (width, height) = logical_grid_size(state.display.size(), &glarea, cols, rows);
+    // whatever's around the terminal (e.g. the header bar) keeps its size
+    let (window_width, window_height) = window.get_size();
+    let allocation = glarea.get_allocation();
+    window.resize(window_width - allocation.width + width, window_height - allocation.height + height);
+}
+
+/// The size of a `cols` by `rows` grid with the padding, in pixels
+fn pixel_grid_size(size: &SizeInfo, cols: usize, rows: usize) -> (f32, f32) {
+    (cols.max(1) as f32 * size.cell_width + 2.0 * size.padding_x,
+     rows.max(1) as f32 * size.cell_height + 2.0 * size.padding_y)
+}
+
+/// Like `pixel_grid_size`, in GTK's logical pixels (the cell metrics are in physical ones)
+fn logical_grid_size(size: &SizeInfo, glarea: &gtk::GLArea, cols: usize, rows: usize) -> (i32, i32) {
+    let scale = glarea.get_scale_factor() as f32;
+    let (width, height) = pixel_grid_size(size, cols, rows);
+    ((width / scale).ceil() as i32, (height / scale).ceil() as i32)
+}
+
+/// Changes the colors (e.g. from a theme picker), keeping the ones the scheme doesn't set.
+///
+/// The padding around the grid uses the new background too. Programs can still change
+/// the colors (and reset them to the config's) with the usual escape sequences.
+///
+/// The scheme's cursor color replaces the one from `set_cursor_color`, like calling it with
+/// `CursorColor::Fixed` would.
+pub fn set_colors(state: &mut State, scheme: &ColorScheme) {
+    use alacritty::ansi::{Handler, NamedColor};
+    let mut terminal = state.terminal.lock();
+    for (index, rgb) in scheme.palette.iter().enumerate() {
+        if let Some(rgb) = *rgb {
+            terminal.set_color(index, rgb);
+        }
+    }
+    let named = [
+        (NamedColor::Foreground, scheme.foreground),
+        (NamedColor::Background, scheme.background),
+    ];
+    for &(name, rgb) in named.iter() {
+        if let Some(rgb) = rgb {
+            terminal.set_color(name as usize, rgb);
+        }
+    }
+    if let Some(rgb) = scheme.cursor {
+        state.cursor_color = CursorColor::Fixed(rgb);
+        apply_cursor_color(&mut terminal, state.cursor_color);
+    }
+    terminal.dirty = true;
+    queue_draw(state.id);
+}
+
+/// Makes the background translucent (the text stays opaque), from 0.0 for fully transparent
+/// to 1.0 for opaque.
+///
+/// This needs a compositor, without one the background stays opaque.
+pub fn set_background_opacity(state: &mut State, alpha: f32) {
+    let glarea = widget(state.id);
+    let composited = glarea.as_ref().and_then(|glarea| glarea.get_screen()).map_or(false, |screen| screen.is_composited());
+    let alpha = if composited {
+        alpha.max(0.0).min(1.0)
+    } else {
+        if alpha < 1.0 {
+            warn!("No compositor, keeping the background opaque");
+        }
+        1.0
+    };
+    // otherwise GTK paints the window's background behind the terminal
+    if let Some(window) = glarea.and_then(|glarea| glarea.get_toplevel()) {
+        window.set_app_paintable(alpha < 1.0);
+    }
+    state.background_opacity = Some(alpha);
+    state.config.set_background_opacity(alpha);
+    state.terminal.lock().dirty = true;
+    queue_draw(state.id);
 }
 
-pub struct IsControlHeld(bool);
+/// The process ID of the shell (or whatever else was started in the terminal),
+/// `None` once it has exited.
+///
+/// Available on Linux and the BSDs (and anything else with `tcgetsid`): the child leads
+/// a new session with the PTY as its terminal, so it's the session ID of the PTY.
+pub fn child_pid(state: &State) -> Option<u32> {
+    if state.child_exited || state.child_hung_up.get() {
+        return None;
+    }
+    let pid = unsafe { libc::tcgetsid(state.pty_fd) };
+    if pid > 0 { Some(pid as u32) } else { None }
+}
+
+/// Sends a signal (e.g. `libc::SIGINT`) to the child's process group, which is the shell
+/// and whatever it started without job control.
+///
+/// Fails if the child has exited. Like the PTY, this needs a Unix-like system.
+pub fn signal_child(state: &State, signal: i32) -> io::Result<()> {
+    let pid = child_pid(state).ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "the child has exited"))?;
+    process::signal_group(pid as i32, signal)
+}
+
+pub fn cursor_color(state: &State) -> CursorColor {
+    state.cursor_color
+}
+
+/// The modes the program in the terminal has turned on, that change how input is sent
+pub fn terminal_modes(state: &State) -> TerminalModes {
+    TerminalModes::from(*state.terminal.lock().mode())
+}
+
+/// Whether text wraps at the right edge (DECAWM), which programs can also turn on and off
+pub fn autowrap(state: &State) -> bool {
+    state.terminal.lock().mode().contains(TermMode::LINE_WRAP)
+}
+
+/// Turns wrapping at the right edge (DECAWM) on or off, like `ESC [ ? 7 h` and `ESC [ ? 7 l`.
+/// Without it, text past the edge overwrites the last column. It's on by default.
+///
+/// This applies to text printed from now on, what's already wrapped stays as it is.
+pub fn set_autowrap(state: &mut State, autowrap: bool) {
+    use alacritty::ansi::{Handler, Mode};
+    let mut terminal = state.terminal.lock();
+    if autowrap {
+        terminal.set_mode(Mode::LineWrap);
+    } else {
+        terminal.unset_mode(Mode::LineWrap);
+    }
+}
+
+/// The cell (as column and row on the screen) at the given position in the widget,
+/// in logical pixels like GTK events use. Positions outside the grid (e.g. in the padding)
+/// give the nearest cell, and both halves of a wide char give the char's cell.
+pub fn cell_at(state: &State, x: f64, y: f64) -> (usize, usize) {
+    let point = point_at(state, x, y);
+    (point.col.0, point.line.0)
+}
+
+fn point_at(state: &State, x: f64, y: f64) -> Point {
+    let size = state.display.size();
+    // the cell metrics are in physical pixels
+    let scale = f64::from(size.dpr);
+    let mut point = mouse::pixels_to_cell(size, x * scale, y * scale);
+    let terminal = state.terminal.lock();
+    let buffer_point = terminal.visible_to_buffer(point);
+    if point.col.0 > 0 && terminal.grid()[buffer_point.line][buffer_point.col].flags.contains(Flags::WIDE_CHAR_SPACER) {
+        point.col = Column(point.col.0 - 1);
+    }
+    point
+}
+
+/// The current font size in points, including zoom.
+pub fn current_font_size(state: &State) -> f32 {
+    state.terminal.lock().font_size.as_f32_pts()
+}
+
+/// The text currently displayed (which may be scrolled back), one line per row.
+pub fn visible_text(state: &State, trim: bool) -> String {
+    let terminal = state.terminal.lock();
+    let grid = terminal.grid();
+    let offset = grid.display_offset();
+    text::lines_text(grid, (offset..offset + grid.num_lines().0).rev(), trim)
+}
+
+/// The whole buffer, scrollback included, one line per row.
+pub fn scrollback_text(state: &State, trim: bool) -> String {
+    let terminal = state.terminal.lock();
+    let grid = terminal.grid();
+    text::lines_text(grid, (0..grid.len()).rev(), trim)
+}
+
+/// Writes the whole buffer (see `scrollback_text`) to a file, with trailing whitespace trimmed.
+pub fn dump_scrollback<P: AsRef<Path>>(state: &State, path: P) -> io::Result<()> {
+    let mut text = scrollback_text(state, true);
+    text.push('\n');
+    fs::write(path, text)
+}
+
+/// Starts logging everything the program prints, byte for byte (or with the escape sequences
+/// stripped, leaving the text), to a file, replacing it. Logging goes on across `respawn`,
+/// and starting it again switches to the new file.
+///
+/// Unlike `dump_scrollback`, this has what scrolled out of the history or got overwritten too.
+/// The file is written in the background, so it lags behind the output a little.
+pub fn start_logging<P: AsRef<Path>>(state: &mut State, path: P, strip_escapes: bool) -> io::Result<()> {
+    state.tap.start_logging(path.as_ref(), strip_escapes)
+}
+
+/// Stops logging, see `start_logging`
+pub fn stop_logging(state: &mut State) {
+    state.tap.stop_logging();
+}
+
+/// Searches the screen and scrollback for `pattern`, scrolling to the first match
+/// and highlighting it by selecting it.
+///
+/// Going backward starts from the bottom, forward from the top. Returns whether
+/// anything was found, or the error if the pattern is not a valid regex.
+pub fn search(state: &mut State, pattern: &str, direction: Direction, options: SearchOptions) -> Result<bool, regex::Error> {
+    state.search = Search::new(&mut state.terminal.lock(), pattern, direction, options)?;
+    queue_draw(state.id);
+    Ok(state.search.is_some())
+}
+
+/// Moves to the next match of the last search, in the same direction, wrapping around.
+///
+/// The search ends (and this does nothing) once its matches have moved: when output scrolls
+/// the buffer, or with `clear`, `set_scrollback_limit` and `reload_config`.
+pub fn search_next(state: &mut State) {
+    if let Some(ref mut search) = state.search {
+        search.step(&mut state.terminal.lock(), false);
+        queue_draw(state.id);
+    }
+}
+
+/// Moves to the next match of the last search, in the opposite direction
+pub fn search_prev(state: &mut State) {
+    if let Some(ref mut search) = state.search {
+        search.step(&mut state.terminal.lock(), true);
+        queue_draw(state.id);
+    }
+}
+
+/// Swaps in a new config (e.g. from a settings dialog), applying it on the next render.
+///
+/// Font and padding changes reload the glyph cache and resize the grid (and the PTY)
+/// just like changing the font size does.
+///
+/// What was set with `set_padding`, `set_word_separators`, `set_background_opacity`,
+/// `set_scrollback_limit` and `set_bell_mode` (or the builder) stays, overriding the new config.
+pub fn reload_config(state: &mut State, config: Config) {
+    state.visual_bell_duration = config.visual_bell().duration();
+    // the history size and the grid can change
+    state.search = None;
+    state.config = config;
+    state.config.set_visual_bell_duration(bell_duration(state.bell_mode, state.visual_bell_duration));
+    if let Some(padding) = state.padding {
+        state.config.set_padding(padding);
+    }
+    if let Some(ref separators) = state.word_separators {
+        state.config.set_semantic_escape_chars(separators);
+    }
+    if let Some(alpha) = state.background_opacity {
+        state.config.set_background_opacity(alpha);
+    }
+    apply_config(state);
+}
+
+/// Changes what happens when the bell rings, see `BellMode`.
+///
+/// The visual modes flash for the config's `visual_bell.duration`, or a short while
+/// if that's 0, the others don't flash at all.
+pub fn set_bell_mode(state: &mut State, mode: BellMode) {
+    state.bell_mode = mode;
+    state.config.set_visual_bell_duration(bell_duration(mode, state.visual_bell_duration));
+    state.terminal.lock().update_config(&state.config);
+}
+
+pub fn bell_mode(state: &State) -> BellMode {
+    state.bell_mode
+}
+
+fn apply_config(state: &mut State) {
+    state.display.update_config(&state.config);
+    {
+        let mut terminal = state.terminal.lock();
+        terminal.update_config(&state.config);
+        if let Some(lines) = state.scrollback_limit {
+            terminal.grid_mut().update_history(lines, &Default::default());
+        }
+        // the new config's cursor colors are only used with `Reverse`
+        if state.cursor_color != CursorColor::Reverse {
+            apply_cursor_color(&mut terminal, state.cursor_color);
+        }
+        terminal.dirty = true;
+    }
+    // forces the glyph cache (and with it, the cell size) to be recomputed
+    let dpr = state.display.size().dpr;
+    state.event_queue.push(Event::HiDPIFactorChanged(dpr));
+    queue_draw(state.id);
+}
+
+/// Changes the shape of the cursor (block, underline or beam).
+///
+/// Programs can still change it with DECSCUSR (`CSI n SP q`), resetting it (`CSI 0 SP q`)
+/// goes back to the config's `cursor_style`. Whether it blinks is up to `cursor_blink`:
+/// Alacritty maps the blinking DECSCUSR variants to the steady ones.
+pub fn set_cursor_style(state: &mut State, style: CursorStyle) {
+    use alacritty::ansi::Handler;
+    let mut terminal = state.terminal.lock();
+    terminal.set_cursor_style(Some(style));
+    terminal.dirty = true;
+    queue_draw(state.id);
+}
+
+/// Changes the color of the cursor, see `CursorColor`.
+///
+/// This and a `set_colors` scheme with a cursor color replace each other, the last call wins.
+/// Either stays through `reload_config`. Programs can change it too (OSC 12), `Reverse`
+/// undoes that.
+pub fn set_cursor_color(state: &mut State, cursor_color: CursorColor) {
+    let mut terminal = state.terminal.lock();
+    apply_cursor_color(&mut terminal, cursor_color);
+    terminal.dirty = true;
+    state.cursor_color = cursor_color;
+    queue_draw(state.id);
+}
+
+fn apply_cursor_color(terminal: &mut Term, cursor_color: CursorColor) {
+    use alacritty::ansi::{Handler, NamedColor};
+    match cursor_color {
+        CursorColor::Fixed(rgb) => {
+            terminal.set_color(NamedColor::Cursor as usize, rgb);
+            terminal.set_color(NamedColor::CursorText as usize, color::contrasting(rgb));
+        },
+        CursorColor::Reverse => {
+            terminal.reset_color(NamedColor::Cursor as usize);
+            terminal.reset_color(NamedColor::CursorText as usize);
+        },
+    }
+}
+
+/// Turns the input method (for composing characters, typing CJK, ...) on or off.
+///
+/// Without it, keys are translated to bytes directly, which is more predictable for
+/// games and other programs reading raw input. It's on by default.
+pub fn set_ime_enabled(state: &mut State, enabled: bool) {
+    if enabled == state.ime_enabled {
+        return;
+    }
+    state.ime_enabled = enabled;
+    if !enabled {
+        state.im.focus_out();
+    } else if state.focused {
+        state.im.focus_in();
+    }
+}
+
+pub fn ime_enabled(state: &State) -> bool {
+    state.ime_enabled
+}
+
+/// Repaints the terminal, e.g. after changing something the widget doesn't watch.
+///
+/// Cheap to call repeatedly: GTK draws at most once per frame, and nothing is queued
+/// when new output already scheduled a redraw.
+pub fn redraw(state: &State) {
+    state.terminal.lock().dirty = true;
+    if !state.draw_pending.load(Ordering::Acquire) {
+        queue_draw(state.id);
+    }
+}
+
+/// Selects everything, scrollback included, e.g. for copying the whole session.
+///
+/// The selection is just its two ends, so this is cheap however long the history is
+/// (copying it is what takes time).
+pub fn select_all(state: &mut State) {
+    state.event_queue.push(Event::SelectAll);
+    queue_draw(state.id);
+}
+
+/// Sets how many lines of history to keep (instead of the config's `scrolling.history`,
+/// 10000 by default), dropping the oldest ones if there are more already.
+///
+/// Each line takes about 16 bytes per column, so 10000 lines of 80 columns are around 13 MB.
+pub fn set_scrollback_limit(state: &mut State, lines: usize) {
+    state.scrollback_limit = Some(lines);
+    state.search = None;
+    let mut terminal = state.terminal.lock();
+    // the selection could end in the dropped lines
+    *terminal.selection_mut() = None;
+    state.block_selection = None;
+    terminal.grid_mut().update_history(lines, &Default::default());
+    terminal.dirty = true;
+    queue_draw(state.id);
+}
+
+/// Wipes the screen and the scrollback, moving the cursor back to the top left.
+///
+/// Unlike running `clear`, this also drops the history. Only the widget's side is cleared,
+/// nothing is sent to the program, so the prompt shows up again with the next output
+/// (e.g. pressing Enter).
+pub fn clear(state: &mut State) {
+    state.event_queue.push(Event::Clear);
+    queue_draw(state.id);
+}
+
+fn clear_terminal(terminal: &mut Term) {
+    use alacritty::ansi::{ClearMode, Handler};
+    terminal.scroll_display(Scroll::Bottom);
+    terminal.clear_screen(ClearMode::Saved);
+    terminal.clear_screen(ClearMode::All);
+    terminal.goto(Line(0), Column(0));
+    *terminal.selection_mut() = None;
+    terminal.dirty = true;
+}
+
+/// Sets the gap between the grid and the edges of the widget, in logical pixels
+/// (the config's `window.padding`).
+///
+/// The grid (and the PTY) is resized to fit on the next render.
+pub fn set_padding(state: &mut State, x: u8, y: u8) {
+    state.padding = Some(Delta { x, y });
+    state.config.set_padding(Delta { x, y });
+    apply_config(state);
+}
+
+/// Sets the characters that end words for double-click selection (the config's
+/// `selection.semantic_escape_chars`, by default ``,│`|:"' ()[]{}<>``), e.g. without `:`
+/// to select `host:port` as one word, or with `/` to select parts of paths.
+///
+/// Keep the space in there. This applies to the next selection.
+pub fn set_word_separators(state: &mut State, separators: &str) {
+    state.word_separators = Some(separators.to_owned());
+    state.config.set_semantic_escape_chars(separators);
+    // only the terminal reads these, nothing needs to be redrawn or resized
+    state.terminal.lock().update_config(&state.config);
+}
+
+/// Writes bytes to the terminal's input, as if they were typed.
+///
+/// Like everything else touching the `State`, this must be called on the GTK main thread.
+/// The bytes are handed to the IO thread right away, no redraw is needed:
+///
+/// ```ignore
+/// glarea.connect_realize(clone!(state => move |_| {
+///     if let Some(ref mut state) = *state.borrow_mut() {
+///         widget::send_str(state, "ls\n");
+///     }
+/// }));
+/// ```
+pub fn send_bytes(state: &mut State, bytes: &[u8]) {
+    use alacritty::event::Notify;
+    state.loop_notifier.notify(bytes.to_vec());
+}
+
+/// Writes a string to the terminal's input, see `send_bytes`.
+pub fn send_str(state: &mut State, s: &str) {
+    send_bytes(state, s.as_bytes());
+}
+
+/// The selected text (what `copy_selection` would copy), without touching the clipboard,
+/// e.g. for searching for it. `None` if nothing is selected.
+pub fn selection_text(state: &State) -> Option<String> {
+    terminal_selection_text(&state.terminal.lock(), state.block_selection.as_ref())
+}
+
+fn terminal_selection_text(terminal: &Term, block: Option<&BlockSelection>) -> Option<String> {
+    let text = match active_block(terminal, block) {
+        Some(block) => Some(block.text(terminal.grid())),
+        None => terminal.selection_to_string(),
+    };
+    text.filter(|s| !s.is_empty())
+}
+
+/// The block selection, if it's the one that's selected
+fn active_block<'a>(terminal: &Term, block: Option<&'a BlockSelection>) -> Option<&'a BlockSelection> {
+    block.filter(|block| !block.is_empty() && terminal.selection().is_none())
+}
+
+/// Copies the current selection to the clipboard, returning the copied text.
+///
+/// Does nothing and returns `None` if nothing is selected.
+pub fn copy_selection(state: &State) -> Option<String> {
+    copy_terminal_selection(&state.terminal.lock(), state.block_selection.as_ref(), "CLIPBOARD")
+}
+
+/// Copies the selection to the given clipboard selection ("CLIPBOARD" or "PRIMARY")
+fn copy_terminal_selection(terminal: &Term, block: Option<&BlockSelection>, selection: &str) -> Option<String> {
+    let text = terminal_selection_text(terminal, block)?;
+    gtk::Clipboard::get(&gdk::Atom::intern(selection)).set_text(&text);
+    Some(text)
+}
+
+/// Prepares pasted text for sending to the terminal.
+///
+/// In bracketed paste mode, the program is told where the paste starts and ends,
+/// otherwise newlines become carriage returns (like pressing Enter).
+fn paste_bytes(text: &str, modes: TerminalModes) -> Vec<u8> {
+    if modes.bracketed_paste {
+        // don't let the pasted text end the paste early
+        let text = text.replace("\x1b", "");
+        let mut bytes = Vec::with_capacity(text.len() + 12);
+        bytes.extend_from_slice(b"\x1b[200~");
+        bytes.extend_from_slice(text.as_bytes());
+        bytes.extend_from_slice(b"\x1b[201~");
+        bytes
+    } else {
+        text.replace("\r\n", "\r").replace("\n", "\r").into_bytes()
+    }
+}
+
+/// Pastes the text from the given clipboard selection ("CLIPBOARD" or "PRIMARY").
+fn paste_from(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea, selection: &str) {
+    // NOTE: wait_for_text runs the main loop, so the state must not be borrowed across it
+    if let Some(text) = gtk::Clipboard::get(&gdk::Atom::intern(selection)).wait_for_text() {
+        paste(state, glarea, text);
+    }
+}
+
+/// Pastes text into the terminal, asking first if it could run commands (see `confirm_multiline_paste`).
+pub fn paste(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea, text: String) {
+    let needs_confirmation = state.borrow().as_ref().map_or(false, |state| {
+        state.confirm_multiline_paste && !terminal_modes(state).bracketed_paste
+            && text.chars().any(|c| c.is_control() && c != '\t')
+    });
+    // NOTE: the dialog runs the main loop too
+    if needs_confirmation && !confirm_paste(glarea, &text) {
+        return;
+    }
+    let mut state = state.borrow_mut();
+    if let Some(ref mut state) = *state {
+        state.event_queue.push(Event::Paste(text));
+        process_events(state, glarea);
+    }
+    glarea.queue_draw();
+}
+
+fn confirm_paste(glarea: &gtk::GLArea, text: &str) -> bool {
+    let window = glarea.get_toplevel().and_then(|w| w.downcast::<gtk::Window>().ok());
+    let dialog = gtk::MessageDialog::new(window.as_ref(), gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+                                         gtk::MessageType::Warning, gtk::ButtonsType::None,
+                                         "The text you're pasting has multiple lines or control characters");
+    let lines = text.lines().count();
+    dialog.set_property_secondary_text(Some(&*format!(
+        "Pasting it ({} line{}) may run commands right away.", lines, if lines == 1 { "" } else { "s" })));
+    dialog.add_button("_Cancel", gtk::ResponseType::Cancel.into());
+    dialog.add_button("_Paste Anyway", gtk::ResponseType::Accept.into());
+    dialog.set_default_response(gtk::ResponseType::Cancel.into());
+    let accepted = dialog.run() == gtk::ResponseType::Accept.into();
+    dialog.destroy();
+    accepted
+}
+
+/// Turns a dropped URI into something to type: local files become (quoted) paths,
+/// anything else is kept as is.
+fn dropped_uri(uri: &str) -> String {
+    match glib::filename_from_uri(uri) {
+        Ok((path, _hostname)) => {
+            let path = path.to_string_lossy();
+            if path.chars().any(|c| !(c.is_alphanumeric() || "/._-+,:@%".contains(c))) {
+                format!("'{}'", path.replace("'", "'\\''"))
+            } else {
+                path.into_owned()
+            }
+        },
+        Err(_) => uri.to_owned(),
+    }
+}
+
+fn perform_action(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea, action: Action) {
+    match action {
+        Action::Paste => return paste_from(state, glarea, "CLIPBOARD"),
+        Action::PastePrimary => return paste_from(state, glarea, "PRIMARY"),
+        _ => (),
+    }
+    let mut state = state.borrow_mut();
+    if let Some(ref mut state) = *state {
+        let event = match action {
+            Action::Copy => Event::Copy,
+            Action::Paste | Action::PastePrimary => return, // handled above, without the state borrowed
+            Action::ZoomIn => Event::ChangeFontSize(state.font_size_step),
+            Action::ZoomOut => Event::ChangeFontSize(-state.font_size_step),
+            Action::ResetZoom => Event::ResetFontSize,
+            Action::SendBytes(bytes) => Event::RawBytes(bytes),
+            Action::ScrollPageUp => Event::ScrollDisplay(Scroll::PageUp),
+            Action::ScrollPageDown => Event::ScrollDisplay(Scroll::PageDown),
+            Action::ScrollToTop => Event::ScrollDisplay(Scroll::Top),
+            Action::ScrollToBottom => Event::ScrollDisplay(Scroll::Bottom),
+            Action::ClearScrollback => Event::Clear,
+            Action::SelectAll => Event::SelectAll,
+            Action::CopyOrSend(bytes) => {
+                let mut terminal = state.terminal.lock();
+                if copy_terminal_selection(&terminal, state.block_selection.as_ref(), "CLIPBOARD").is_some() {
+                    // so that pressing it again does the other thing
+                    *terminal.selection_mut() = None;
+                    state.block_selection = None;
+                    terminal.dirty = true;
+                    Event::Blank
+                } else {
+                    Event::RawBytes(bytes)
+                }
+            },
+        };
+        state.event_queue.push(event);
+        process_events(state, glarea);
+    }
+}
+
+fn push_mouse_event(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea, event: &gdk::EventButton, button_state: ButtonState) {
+    // GDK synthesizes these on top of the regular presses, we count clicks ourselves
+    match event.get_event_type() {
+        gdk::EventType::DoubleButtonPress | gdk::EventType::TripleButtonPress => return,
+        _ => (),
+    }
+    let mut state = state.borrow_mut();
+    if let Some(ref mut state) = *state {
+        // event coordinates are in logical pixels, the display works in physical ones
+        let scale = glarea.get_scale_factor() as f64;
+        let (x, y) = event.get_position();
+        state.event_queue.push(Event::MouseInput {
+            button: event.get_button(), x: x * scale, y: y * scale,
+            state: button_state, mods: event.get_state(), time: event.get_time(),
+        });
+        process_events(state, glarea);
+    }
+    glarea.queue_draw();
+}
+
+/// Tells the program about focus changes, if it asked for that (`CSI ? 1004 h`),
+/// and redraws the cursor
+fn report_focus(state: &mut State, bytes: &[u8]) {
+    state.terminal.lock().dirty = true;
+    if terminal_modes(state).focus_events && !state.child_exited {
+        send_bytes(state, bytes);
+    }
+}
+
+/// How long the cursor stays on (or off) while blinking, in ms.
+///
+/// Alacritty's config has no blink settings, so this follows GTK's.
+fn cursor_blink_interval() -> u32 {
+    let blink_time = gtk::Settings::get_default().map_or(1200, |settings| settings.get_property_gtk_cursor_blink_time());
+    (blink_time.max(200) / 2) as u32
+}
+
+/// Shows the cursor and (re)starts blinking it, so that it stays visible while typing
+fn restart_blinking(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea) {
+    let mut state_ref = state.borrow_mut();
+    if let Some(ref mut state_ref) = *state_ref {
+        state_ref.stop_blinking();
+        if !state_ref.cursor_blink {
+            return;
+        }
+        state_ref.blink_timer = Some(gtk::timeout_add(cursor_blink_interval(), clone!(state, glarea => move || {
+            let mut state = state.borrow_mut();
+            if let Some(ref mut state) = *state {
+                if !state.cursor_blink {
+                    // the timer removes itself by returning false
+                    state.blink_timer = None;
+                    state.cursor_visible = true;
+                } else {
+                    state.cursor_visible = !state.cursor_visible;
+                }
+                state.terminal.lock().dirty = true;
+                glarea.queue_draw();
+                return glib::Continue(state.blink_timer.is_some());
+            }
+            glib::Continue(false)
+        })));
+    }
+}
+
+/// Builds the right click menu, its items work like the corresponding shortcuts
+/// Builds the right click menu, returning it with its Copy and Paste items
+fn build_context_menu(glarea: &gtk::GLArea, state: &Rc<RefCell<Option<State>>>) -> (gtk::Menu, gtk::MenuItem, gtk::MenuItem) {
+    let menu = gtk::Menu::new();
+    menu.set_attach_widget(Some(glarea));
+    let push = |label: &str, event: fn() -> Event| {
+        let item = gtk::MenuItem::new_with_mnemonic(label);
+        item.connect_activate(clone!(state, glarea => move |_| {
+            if let Some(ref mut state) = *state.borrow_mut() {
+                state.event_queue.push(event());
+            }
+            glarea.queue_draw();
+        }));
+        menu.append(&item);
+        item
+    };
+    let copy = push("_Copy", || Event::Copy);
+    let paste = gtk::MenuItem::new_with_mnemonic("_Paste");
+    paste.connect_activate(clone!(state, glarea => move |_| {
+        paste_from(&state, &glarea, "CLIPBOARD");
+        glarea.queue_draw();
+    }));
+    menu.append(&paste);
+    menu.append(&gtk::SeparatorMenuItem::new());
+    push("Select _All", || Event::SelectAll);
+    push("C_lear", || Event::Clear);
+    menu.show_all();
+    (menu, copy, paste)
+}
+
+/// Shows the right click menu, with Copy and Paste only enabled if there's something to copy or paste
+fn popup_context_menu(state: &Rc<RefCell<Option<State>>>, event: &gdk::EventButton) {
+    let (menu, copy_item, paste_item, has_selection) = match *state.borrow() {
+        Some(ref state) => (state.context_menu.clone(), state.copy_item.clone(), state.paste_item.clone(),
+                            selection_text(state).is_some()),
+        None => return,
+    };
+    // NOTE: like wait_for_text, this runs the main loop, so the state must not be borrowed
+    let can_paste = gtk::Clipboard::get(&gdk::Atom::intern("CLIPBOARD")).wait_is_text_available();
+    copy_item.set_sensitive(has_selection);
+    paste_item.set_sensitive(can_paste);
+    menu.popup_easy(event.get_button(), event.get_time());
+}
+
+/// The URL under the pointer, if any
+fn url_under_pointer(state: &Rc<RefCell<Option<State>>>, (x, y): (f64, f64)) -> Option<String> {
+    let state = state.borrow();
+    let state = state.as_ref()?;
+    url::url_at(&state.urls, point_at(state, x, y)).map(|url| url.url.clone())
+}
+
+/// Tracks which cell the pointer is over, underlining URLs (on the next render)
+/// and showing the hand cursor over them
+fn hover(state: &Rc<RefCell<Option<State>>>, glarea: &gtk::GLArea, position: Option<(f64, f64)>) {
+    let mut state = state.borrow_mut();
+    if let Some(ref mut state) = *state {
+        let point = position.map(|(x, y)| point_at(state, x, y));
+        let old_url = state.hover_point.and_then(|p| url::url_at(&state.urls, p)).cloned();
+        let new_url = point.and_then(|p| url::url_at(&state.urls, p)).cloned();
+        state.hover_point = point;
+        if old_url == new_url {
+            return;
+        }
+        if let Some(window) = glarea.get_window() {
+            let cursor = new_url.as_ref().and_then(|_| gdk::Cursor::new_from_name(&window.get_display(), "pointer"));
+            window.set_cursor(cursor.as_ref());
+        }
+        state.terminal.lock().dirty = true;
+    }
+    glarea.queue_draw();
+}
+
+/// Loads the user's Alacritty config file (e.g. `~/.config/alacritty/alacritty.yml`),
+/// falling back to the defaults if there isn't one or it's broken.
+pub fn load_user_config() -> Config {
+    match Config::installed_config() {
+        Some(path) => Config::load_from(&*path).unwrap_or_else(|err| {
+            error!("Could not load config from {:?}: {}, using the defaults", path, err);
+            Config::default()
+        }),
+        None => Config::default(),
+    }
+}
+
+/// Creates a GLArea that runs an Alacritty terminal emulator, configured from the user's
+/// Alacritty config.
+///
+/// Eventually should be a GObject subclass, usable outside of Rust.
+pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+    alacritty_widget_builder().header_bar(header_bar).build(window)
+}
+
+/// Options for creating a terminal widget, see `alacritty_widget_builder`.
+#[derive(Default)]
+pub struct GalacrittyBuilder {
+    header_bar: Option<gtk::HeaderBar>,
+    config: Option<Config>,
+    font: Option<(String, f32)>,
+    options: WidgetOptions,
+}
+
+/// Creation options that aren't part of the Alacritty config
+#[derive(Default)]
+struct WidgetOptions {
+    scrollback_lines: Option<usize>,
+    padding: Option<Delta<u8>>,
+    word_separators: Option<String>,
+    working_directory: Option<PathBuf>,
+    command: Option<(String, Vec<String>)>,
+    env: HashMap<String, String>,
+    on_error: Option<Rc<dyn Fn(&str)>>,
+    on_ready: Option<Rc<dyn Fn()>>,
+}
+
+/// Starts building a terminal widget:
+///
+/// ```ignore
+/// let (glarea, state) = widget::alacritty_widget_builder()
+///     .header_bar(header_bar)
+///     .font("Fira Code", 12.0)
+///     .build(window);
+/// ```
+pub fn alacritty_widget_builder() -> GalacrittyBuilder {
+    GalacrittyBuilder::default()
+}
+
+impl GalacrittyBuilder {
+    /// Shows the terminal's title in the given header bar, instead of the window's title
+    pub fn header_bar(mut self, header_bar: gtk::HeaderBar) -> Self {
+        self.header_bar = Some(header_bar);
+        self
+    }
+
+    /// Uses the given config instead of the user's Alacritty config
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = Some(config);
+        self
+    }
 
-pub enum Event {
-    Blank,
-    CharInput(char, IsControlHeld),
-    StringInput(String),
-    StrInput(&'static str),
-    WindowResized(u32, u32),
-    HiDPIFactorChanged(f32),
-    ChangeFontSize(i8),
-    ResetFontSize,
+    /// Sets the font family (for all faces) and size in points
+    pub fn font(mut self, family: &str, size: f32) -> Self {
+        self.font = Some((family.to_owned(), size));
+        self
+    }
+
+    /// Sets how many lines of history to keep, overriding the config's `scrolling.history`
+    /// (see `set_scrollback_limit`)
+    pub fn scrollback_lines(mut self, lines: usize) -> Self {
+        self.options.scrollback_lines = Some(lines);
+        self
+    }
+
+    /// Starts the shell in the given directory instead of the current one
+    pub fn working_directory<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.options.working_directory = Some(path.as_ref().to_owned());
+        self
+    }
+
+    /// Runs the given program instead of the user's shell, e.g. `.command("python3", &[])`.
+    ///
+    /// The child exit handling applies just the same. If the program can't be found,
+    /// nothing is started and the widget fails like it does without GL (see `on_error`).
+    pub fn command(mut self, program: &str, args: &[&str]) -> Self {
+        self.options.command = Some((program.to_owned(), args.iter().map(|&arg| arg.to_owned()).collect()));
+        self
+    }
+
+    /// Sets an environment variable for the child process.
+    ///
+    /// The child inherits the environment of our process, with `TERM` set to `xterm-256color`,
+    /// `COLORTERM` to `truecolor` and the config's `env` added. Variables set here win over
+    /// all of them, so this can also override `TERM`. Our own environment isn't touched.
+    pub fn env(mut self, key: &str, value: &str) -> Self {
+        self.options.env.insert(key.to_owned(), value.to_owned());
+        self
+    }
+
+    /// Sets a callback for when the terminal can't be started (e.g. there's no OpenGL),
+    /// called with the error message.
+    ///
+    /// The widget shows the message instead of the terminal, and its state stays `None`.
+    pub fn on_error<F: Fn(&str) + 'static>(mut self, on_error: F) -> Self {
+        self.options.on_error = Some(Rc::new(on_error));
+        self
+    }
+
+    /// Sets a callback for when the terminal is up and running, i.e. the state is there
+    /// to be configured (and stays there until the widget is unrealized).
+    ///
+    /// Everything is set up when the widget is realized, on the main thread: the renderer
+    /// needs the GL context, which GTK only has there, and neither `Term` nor `Display` can
+    /// move between threads. The child is only forked there, so it starting up doesn't stall
+    /// the UI (the terminal is just empty until it prints something).
+    pub fn on_ready<F: Fn() + 'static>(mut self, on_ready: F) -> Self {
+        self.options.on_ready = Some(Rc::new(on_ready));
+        self
+    }
+
+    /// Sets the gap between the grid and the edges of the widget, see `set_padding`
+    pub fn padding(mut self, x: u8, y: u8) -> Self {
+        self.options.padding = Some(Delta { x, y });
+        self
+    }
+
+    /// Sets the characters that end words for double-click selection, see `set_word_separators`
+    pub fn word_separators(mut self, separators: &str) -> Self {
+        self.options.word_separators = Some(separators.to_owned());
+        self
+    }
+
+    /// Creates the widget for the given window, which is also closed when the child exits
+    /// (unless `on_child_exit` is set)
+    pub fn build(self, window: gtk::ApplicationWindow) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+        let mut config = self.config.unwrap_or_else(load_user_config);
+        if let Some((family, size)) = self.font {
+            config.set_font(font::from_family(&family, size));
+        }
+        if let Some(padding) = self.options.padding {
+            config.set_padding(padding);
+        }
+        if let Some(ref separators) = self.options.word_separators {
+            config.set_semantic_escape_chars(separators);
+        }
+        build_widget(window, self.header_bar, config, self.options)
+    }
 }
 
-struct Notifier;
+/// How long the size stays on the screen after resizing stops
+const RESIZE_OVERLAY_TIMEOUT_MS: u32 = 1000;
 
-impl WindowNotifier for Notifier {
-    fn notify(&self) {
-        // NOTE: not gtk::idle_add, that one checks if we're on the main thread
-        let _ = glib::idle_add(|| {
-            GLOBAL.with(|global| {
-                if let Some(ref glarea) = *global.borrow() {
-                    glarea.queue_draw();
+/// Puts the widget in an overlay that shows the grid size (like `80×24`) while resizing,
+/// like GNOME Terminal does. Add the returned overlay to the window instead of the widget.
+///
+/// `on_resize` is still free for the embedder to use.
+pub fn with_resize_overlay(glarea: &gtk::GLArea, state: &Rc<RefCell<Option<State>>>) -> gtk::Overlay {
+    let overlay = gtk::Overlay::new();
+    overlay.add(glarea);
+    let label = gtk::Label::new(None);
+    label.get_style_context().add_class("osd");
+    label.set_halign(gtk::Align::Center);
+    label.set_valign(gtk::Align::Center);
+    label.set_no_show_all(true);
+    overlay.add_overlay(&label);
+    let hide_timer: Rc<RefCell<Option<glib::SourceId>>> = Rc::new(RefCell::new(None));
+    glarea.connect_realize(clone!(state, label, hide_timer => move |_| {
+        if let Some(ref mut state) = *state.borrow_mut() {
+            // the first size is the initial one, not a resize
+            let initial = Cell::new(true);
+            state.show_size = Some(Rc::new(clone!(label, hide_timer => move |cols, rows| {
+                if initial.replace(false) {
+                    return;
                 }
-            });
-            glib::Continue(false)
-        });
+                label.set_text(&format!("{}×{}", cols, rows));
+                label.show();
+                if let Some(hide_timer) = hide_timer.borrow_mut().take() {
+                    glib::source_remove(hide_timer);
+                }
+                *hide_timer.borrow_mut() = Some(gtk::timeout_add(RESIZE_OVERLAY_TIMEOUT_MS, clone!(label, hide_timer => move || {
+                    *hide_timer.borrow_mut() = None;
+                    label.hide();
+                    glib::Continue(false)
+                })));
+            })));
+        }
+    }));
+    overlay
+}
+
+/// Creates a GLArea that runs an Alacritty terminal emulator with the given config.
+pub fn alacritty_widget_with_config(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar, config: Config) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+    alacritty_widget_builder().header_bar(header_bar).config(config).build(window)
+}
+
+/// Creates a terminal widget (with the user's config) whose shell starts in the given directory,
+/// like "open terminal here" in a file manager.
+///
+/// If the directory doesn't exist, the shell starts in the home directory.
+pub fn alacritty_widget_in_dir<P: AsRef<Path>>(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar, path: P) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+    alacritty_widget_builder().header_bar(header_bar).working_directory(path).build(window)
+}
+
+/// The most arrow keys a click sends, so that a click can't flood the program
+const MAX_CLICK_ARROWS: isize = 1000;
+
+/// The arrow keys that move the cursor to the given cell, for `click_to_move_cursor`.
+///
+/// Lines count as wrapped into each other, that's how readline moves through long ones.
+/// Clicks outside the cursor's line (with whatever it wraps into) send nothing, the program
+/// would take the arrows for history or menu navigation.
+fn arrows_to(terminal: &Term, target: Point) -> Vec<u8> {
+    let cursor = terminal.cursor().point;
+    let grid = terminal.grid();
+    let cols = grid.num_cols().0;
+    let wraps = |line: usize| {
+        let buffer_line = terminal.visible_to_buffer(Point::new(Line(line), Column(0))).line;
+        grid[buffer_line][Column(cols - 1)].flags.contains(Flags::WRAPLINE)
+    };
+    let mut top = cursor.line.0;
+    while top > 0 && wraps(top - 1) {
+        top -= 1;
+    }
+    let mut bottom = cursor.line.0;
+    while bottom + 1 < grid.num_lines().0 && wraps(bottom) {
+        bottom += 1;
     }
+    if target.line.0 < top || target.line.0 > bottom {
+        return Vec::new();
+    }
+    let delta = (target.line.0 as isize - cursor.line.0 as isize) * cols as isize
+        + target.col.0 as isize - cursor.col.0 as isize;
+    if delta.abs() > MAX_CLICK_ARROWS {
+        return Vec::new();
+    }
+    let key = if delta > 0 { gdk::enums::key::Right } else { gdk::enums::key::Left };
+    let arrow = keys::keyval_to_escape(key, Mod::empty(), *terminal.mode()).unwrap_or_default();
+    (0..delta.abs()).flat_map(|_| arrow.iter().cloned()).collect()
 }
 
-pub struct State {
-    pub config: Config,
-    pub display: Display,
-    terminal: Arc<FairMutex<Term>>,
-    pty_fd: RawFd,
-    loop_notifier: event_loop::Notifier,
-    io_thread: JoinHandle<(EventLoop<Pty>, event_loop::State)>,
-    pub event_queue: Vec<Event>,
+/// How many lines to scroll by for a selection dragged to `y` (in physical pixels):
+/// up past the top, down past the bottom, 0 inside the grid
+fn autoscroll_direction(size: &SizeInfo, y: f64) -> isize {
+    if y < size.padding_y as f64 {
+        1
+    } else if y >= (size.height - size.padding_y) as f64 {
+        -1
+    } else {
+        0
+    }
+}
+
+fn send_display_command(display: &Display, command: DisplayCommand) {
+    if display.command_channel().send(command).is_err() {
+        error!("The display is gone, could not send it a command");
+    }
+}
+
+/// Checks the requested working directory, falling back to $HOME
+fn working_directory(path: &Path) -> Option<PathBuf> {
+    if path.is_dir() {
+        return Some(path.to_owned());
+    }
+    let home = env::var_os("HOME").map(PathBuf::from);
+    warn!("Working directory {:?} does not exist, using {:?}", path, home);
+    home
 }
 
-/// Creates a GLArea that runs an Alacritty terminal emulator.
+/// Whether the program is a path to an executable or can be found in $PATH
+fn program_exists(program: &str) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    let is_executable = |path: &Path| path.metadata().map_or(false, |m| m.is_file() && m.permissions().mode() & 0o111 != 0);
+    if program.contains('/') {
+        return is_executable(Path::new(program));
+    }
+    env::var_os("PATH").map_or(false, |paths| env::split_paths(&paths).any(|dir| is_executable(&dir.join(program))))
+}
+
+/// Handles the queued input events, writing to the PTY right away.
 ///
-/// Eventually should be a GObject subclass, usable outside of Rust.
-pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderBar) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
+/// Called as soon as input arrives (typing shouldn't wait for the next frame to be echoed)
+/// and before rendering, for whatever's left.
+fn process_events(state: &mut State, glarea: &gtk::GLArea) {
+    if state.event_queue.is_empty() {
+        return;
+    }
+    let mut terminal = state.terminal.lock();
+    for event in state.event_queue.drain(..) {
+        if event.is_input() && state.scroll_on_keystroke {
+            terminal.scroll_display(Scroll::Bottom);
+        }
+        match event {
+            Event::Blank => (),
+            Event::CharInput(c, IsControlHeld(is_ctrl)) => {
+                use alacritty::event::Notify;
+                state.loop_notifier.notify(keys::char_input(c, is_ctrl));
+            },
+            Event::StringInput(s) => {
+                use alacritty::event::Notify;
+                state.loop_notifier.notify(s.as_bytes().to_vec());
+            },
+            Event::RawBytes(bytes) => {
+                use alacritty::event::Notify;
+                state.loop_notifier.notify(bytes);
+            },
+            Event::WindowResized(w, h) => {
+                // a grid with no cells at all confuses both the PTY and the cell math
+                let (min_width, min_height) = pixel_grid_size(state.display.size(), 1, 1);
+                let (w, h) = (w.max(min_width.ceil() as u32), h.max(min_height.ceil() as u32));
+                send_display_command(&state.display, DisplayCommand::NewSize(w, h));
+                terminal.dirty = true;
+            },
+            Event::HiDPIFactorChanged(dpr) => {
+                // handle_resize picks this up on the next render: it rasterizes the glyphs again
+                // at the new DPI, recomputes the cell size and resizes the grid and the PTY
+                // (GTK sends the new size in physical pixels separately, via connect_resize)
+                send_display_command(&state.display, DisplayCommand::NewHiDPIFactor(dpr));
+                terminal.dirty = true;
+            },
+            Event::ChangeFontSize(delta) => {
+                let size = terminal.font_size.as_f32_pts();
+                let new_size = (size + delta).max(MIN_FONT_SIZE).min(MAX_FONT_SIZE);
+                terminal.change_font_size(new_size - size);
+                state.font_size_changed = true;
+            },
+            Event::ResetFontSize => {
+                terminal.reset_font_size();
+                state.font_size_changed = true;
+            },
+            Event::MouseInput { button, x, y, state: button_state, mods, time } => {
+                let modes = TerminalModes::from(*terminal.mode());
+                // shift bypasses mouse reporting, like in xterm
+                if modes.mouse_tracking.is_some() && !mods.contains(Mod::SHIFT_MASK) {
+                    if let Some(code) = mouse::button_code(button) {
+                        let point = mouse::pixels_to_cell(state.display.size(), x, y);
+                        if let Some(bytes) = mouse::report(code, point, mods, button_state, modes.sgr_mouse) {
+                            use alacritty::event::Notify;
+                            state.loop_notifier.notify(bytes);
+                        }
+                    }
+                } else if button == 1 {
+                    state.mouse.left_pressed = button_state == ButtonState::Pressed;
+                    let visible_point = mouse::pixels_to_cell(state.display.size(), x, y);
+                    if state.mouse.left_pressed {
+                        let size = state.display.size();
+                        state.mouse.press_point = Some(visible_point);
+                        let clicks = state.mouse.register_click(time, state.double_click_timeout);
+                        let point = terminal.visible_to_buffer(mouse::pixels_to_cell(size, x, y));
+                        let side = mouse::wide_char_side(&terminal.grid()[point.line][point.col], mouse::pixels_to_side(size, x));
+                        let block = active_block(&terminal, state.block_selection.as_ref()).is_some();
+                        let extending = clicks == 1 && mods.contains(Mod::SHIFT_MASK) && (terminal.selection().is_some() || block);
+                        if extending {
+                            // the selection keeps its kind, so extending a double-click one
+                            // still selects whole words
+                            if let Some(ref mut selection) = *terminal.selection_mut() {
+                                selection.update(point, side);
+                            } else if let Some(ref mut block) = state.block_selection {
+                                block.update(point);
+                            }
+                        } else if clicks == 1 && !state.block_selection_mods.is_empty() && mods.contains(state.block_selection_mods) {
+                            *terminal.selection_mut() = None;
+                            state.block_selection = Some(BlockSelection::new(point));
+                        } else {
+                            state.block_selection = None;
+                            *terminal.selection_mut() = Some(match clicks {
+                                2 => Selection::semantic(point),
+                                3 => Selection::lines(point),
+                                _ => Selection::simple(point, side),
+                            });
+                        }
+                        terminal.dirty = true;
+                    } else {
+                        // like everywhere else on X11 (and Wayland), selecting is copying
+                        let _ = copy_terminal_selection(&terminal, state.block_selection.as_ref(), "PRIMARY");
+                        let clicked = state.mouse.press_point.take() == Some(visible_point) && state.mouse.click_count() == 1;
+                        if clicked && state.click_to_move_cursor && !mods.intersects(Mod::SHIFT_MASK | Mod::CONTROL_MASK)
+                            && !modes.alternate_screen && terminal.grid().display_offset() == 0 {
+                            let arrows = arrows_to(&terminal, visible_point);
+                            if !arrows.is_empty() {
+                                use alacritty::event::Notify;
+                                state.loop_notifier.notify(arrows);
+                            }
+                        }
+                    }
+                }
+            },
+            Event::MouseMotion { x, y, button, mods } => {
+                let modes = TerminalModes::from(*terminal.mode());
+                if modes.mouse_tracking.is_some() && !mods.contains(Mod::SHIFT_MASK) {
+                    // like xterm, only moving to another cell counts, so that there isn't
+                    // a report for every pixel
+                    let point = mouse::pixels_to_cell(state.display.size(), x, y);
+                    if let Some(code) = mouse::motion_code(modes.mouse_tracking, button).filter(|_| state.mouse.last_motion_report != Some(point)) {
+                        state.mouse.last_motion_report = Some(point);
+                        if let Some(bytes) = mouse::report(code, point, mods, ButtonState::Pressed, modes.sgr_mouse) {
+                            use alacritty::event::Notify;
+                            state.loop_notifier.notify(bytes);
+                        }
+                    }
+                    continue;
+                }
+                if !state.mouse.left_pressed {
+                    continue;
+                }
+                let size = *state.display.size();
+                // dragging past the edges scrolls the viewport to select more
+                // (and keeps scrolling with the autoscroll timer while the pointer stays there)
+                match autoscroll_direction(&size, y) {
+                    0 => (),
+                    lines => terminal.scroll_display(Scroll::Lines(lines)),
+                }
+                let point = terminal.visible_to_buffer(mouse::pixels_to_cell(&size, x, y));
+                let side = mouse::wide_char_side(&terminal.grid()[point.line][point.col], mouse::pixels_to_side(&size, x));
+                if let Some(ref mut selection) = *terminal.selection_mut() {
+                    selection.update(point, side);
+                } else if let Some(ref mut block) = state.block_selection {
+                    block.update(point);
+                }
+                terminal.dirty = true;
+            },
+            Event::Scroll { lines, x, y, mods } => {
+                let modes = TerminalModes::from(*terminal.mode());
+                if modes.mouse_tracking.is_some() && !mods.contains(Mod::SHIFT_MASK) {
+                    // the wheel is buttons 4 and 5, pressed once per notch
+                    let code = if lines > 0 { 64 } else { 65 };
+                    let point = mouse::pixels_to_cell(state.display.size(), x, y);
+                    let mut bytes = Vec::new();
+                    for _ in 0..lines.abs() {
+                        if let Some(report) = mouse::report(code, point, mods, ButtonState::Pressed, modes.sgr_mouse) {
+                            bytes.extend_from_slice(&report);
+                        }
+                    }
+                    if !bytes.is_empty() {
+                        use alacritty::event::Notify;
+                        state.loop_notifier.notify(bytes);
+                    }
+                } else if modes.alternate_screen && modes.alternate_scroll {
+                    // xterm's alternate scroll mode (1007): the alternate screen has no scrollback,
+                    // so pagers etc. get arrow keys (the config's faux_multiplier per notch, 3 by default)
+                    let lines = lines * state.config.scrolling().faux_multiplier as i32;
+                    let cmd = if lines > 0 { b'A' } else { b'B' };
+                    let mut bytes = Vec::with_capacity(lines.abs() as usize * 3);
+                    for _ in 0..lines.abs() {
+                        bytes.extend_from_slice(&[0x1b, b'O', cmd]);
+                    }
+                    if !bytes.is_empty() {
+                        use alacritty::event::Notify;
+                        state.loop_notifier.notify(bytes);
+                    }
+                } else {
+                    let lines = lines * state.config.scrolling().multiplier as i32;
+                    terminal.scroll_display(Scroll::Lines(lines as isize));
+                }
+            },
+            Event::ScrollDisplay(scroll) => {
+                terminal.scroll_display(scroll);
+            },
+            Event::Copy => {
+                let _ = copy_terminal_selection(&terminal, state.block_selection.as_ref(), "CLIPBOARD");
+            },
+            Event::Paste(text) => {
+                use alacritty::event::Notify;
+                state.loop_notifier.notify(paste_bytes(&text, TerminalModes::from(*terminal.mode())));
+            },
+            Event::SelectAll => {
+                let grid = terminal.grid();
+                let top = Point::new(grid.len() - 1, Column(0));
+                let bottom = Point::new(0, grid.num_cols() - 1);
+                let mut selection = Selection::simple(top, Side::Left);
+                selection.update(bottom, Side::Right);
+                *terminal.selection_mut() = Some(selection);
+                terminal.dirty = true;
+            },
+            Event::Clear => {
+                clear_terminal(&mut terminal);
+                state.block_selection = None;
+                state.hyperlinks.clear();
+                state.search = None;
+            },
+            Event::SetFont(family) => {
+                // the rasterizer would just panic on a missing font
+                if !glarea.get_pango_context().map_or(false, |ctx| font::family_exists(&ctx, &family)) {
+                    warn!("Font family {:?} not found, keeping the current font", family);
+                    continue;
+                }
+                let size = state.config.font().size.as_f32_pts();
+                state.config.set_font(font::from_family(&family, size));
+                // reloads the glyph cache, which recomputes the cell size and resizes the PTY
+                send_display_command(&state.display, DisplayCommand::NewHiDPIFactor(glarea.get_scale_factor() as f32));
+                terminal.dirty = true;
+            },
+        }
+    }
+}
+
+fn build_widget(window: gtk::ApplicationWindow, header_bar: Option<gtk::HeaderBar>, config: Config, options: WidgetOptions) -> (gtk::GLArea, Rc<RefCell<Option<State>>>) {
     let glarea = gtk::GLArea::new();
+    // for set_background_opacity, both have to be set up before they're realized
+    glarea.set_has_alpha(true);
+    if !window.get_realized() {
+        if let Some(visual) = window.get_screen().and_then(|screen| screen.get_rgba_visual()) {
+            window.set_visual(Some(&visual));
+        }
+    }
 
     let im = gtk::IMMulticontext::new();
     im.set_use_preedit(false);
 
     let state: Rc<RefCell<Option<State>>> = Rc::new(RefCell::new(None));
+    let id = NEXT_WIDGET_ID.fetch_add(1, Ordering::Relaxed);
+    // the config lives here while the widget is not realized
+    let config = Rc::new(RefCell::new(Some(config)));
+    let widget_options = Rc::new(options);
 
-    glarea.connect_realize(clone!(state, im => move |glarea| {
-        let mut state = state.borrow_mut();
+    let (context_menu, copy_item, paste_item) = build_context_menu(&glarea, &state);
+    let set_title: Rc<dyn Fn(&str)> = match header_bar {
+        Some(header_bar) => Rc::new(move |title: &str| header_bar.set_title(title)),
+        None => {
+            let window = window.clone();
+            Rc::new(move |title: &str| window.set_title(title))
+        },
+    };
+
+    glarea.connect_realize(clone!(state, config, widget_options, im, context_menu, copy_item, paste_item, set_title => move |glarea| {
+        register_widget(id, glarea, &state);
         im.set_client_window(glarea.get_window().as_ref());
+        // the widget shows the message instead of rendering, and the state stays empty
+        let fail = |message: String| {
+            error!("{}", message);
+            glarea.set_error(Some(&glib::Error::new(gdk::GLError::NotAvailable, &message)));
+            if let Some(ref on_error) = widget_options.on_error {
+                on_error(&message);
+            }
+        };
         glarea.make_current();
+        if let Some(err) = glarea.get_error() {
+            return fail(format!("Could not create a GL context: {}", err));
+        }
 
+        let lib = match DynamicLibrary::open(None) {
+            Ok(lib) => lib,
+            Err(err) => return fail(format!("Could not load the GL library: {}", err)),
+        };
         epoxy::load_with(|s| {
             unsafe {
-                match DynamicLibrary::open(None).unwrap().symbol(s) {
+                match lib.symbol(s) {
                     Ok(v) => v,
                     Err(_) => ptr::null(),
                 }
@@ -92,242 +2101,502 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
         });
         gl::load_with(epoxy::get_proc_addr);
 
-        let config = Config::default();
+        let config_slot = config.clone();
+        let mut config = config.borrow_mut().take().unwrap_or_else(load_user_config);
         let mut options = cli::Options::default();
         options.print_events = true;
+        options.working_dir = widget_options.working_directory.as_ref().and_then(|path| working_directory(path));
+        if let Some((ref program, ref args)) = widget_options.command {
+            // Alacritty would exit the whole process if it can't spawn the command
+            if !program_exists(program) {
+                *config_slot.borrow_mut() = Some(config);
+                return fail(format!("Command not found: {}", program));
+            }
+            options.command = Some(Shell::new_with_args(program.clone(), args.clone()));
+        }
 
-        let display = Display::new(
+        // NOTE: the renderer panics on some failures (e.g. fonts), which must not unwind into GTK
+        let display = panic::catch_unwind(panic::AssertUnwindSafe(|| Display::new(
             &config,
             InitialSize::Cells(config.dimensions()),
             glarea.get_scale_factor() as f32
-        ).expect("Display::new");
+        )));
+        let display = match display {
+            Ok(Ok(display)) => display,
+            result => {
+                // keep the config for the next try
+                *config_slot.borrow_mut() = Some(config);
+                return fail(match result {
+                    Ok(Err(err)) => format!("Could not initialize the renderer: {}", err),
+                    _ => "Could not initialize the renderer".to_owned(),
+                });
+            },
+        };
 
-        let terminal = Term::new(&config, display.size().to_owned());
+        let visual_bell_duration = config.visual_bell().duration();
+        config.set_visual_bell_duration(bell_duration(BellMode::Audible, visual_bell_duration));
+        let mut terminal = Term::new(&config, display.size().to_owned());
+        if let Some(lines) = widget_options.scrollback_lines {
+            terminal.grid_mut().update_history(lines, &Default::default());
+        }
         let terminal = Arc::new(FairMutex::new(terminal));
 
-        let pty = tty::new(&config, &options, &display.size(), None);
-        let pty_fd = pty.fd.as_raw_fd();
-
-        let event_loop = EventLoop::new(
-            Arc::clone(&terminal),
-            Box::new(Notifier),
-            pty,
-            options.ref_test,
-        );
+        let tap = Arc::new(Tap::new());
+        let child_hung_up = Rc::new(Cell::new(false));
+        let draw_pending = Arc::new(AtomicBool::new(false));
+        let (pty_fd, loop_notifier, io_thread) = spawn_child(
+            id, &mut config, &options, &widget_options.env, &terminal, display.size(), &tap, &draw_pending);
+        let child_watch = watch_child(id, pty_fd, child_hung_up.clone());
 
-        let loop_notifier = event_loop::Notifier(event_loop.channel());
-        let io_thread = event_loop.spawn(None);
-
-        *state = Some(State {
-            config, display, terminal, pty_fd,
-            loop_notifier, io_thread,
-            event_queue: Vec::new()
+        let double_click_timeout = config.mouse().double_click.threshold;
+        let grid_size = (display.size().cols().0, display.size().lines().0);
+        *state.borrow_mut() = Some(State {
+            config, display, terminal, options, id, pty_fd,
+            env: widget_options.env.clone(),
+            loop_notifier, io_thread: Some(io_thread), tap,
+            child_watch: Some(child_watch), child_hung_up, draw_pending, output_since_render: false,
+            event_queue: Vec::new(),
+            scroll_accum: 0.0,
+            mouse: mouse::Mouse::default(),
+            double_click_timeout,
+            alt_sends_escape: true,
+            backspace_sends_delete: true,
+            csi_u_keys: false,
+            scroll_on_keystroke: true,
+            scroll_on_output: false,
+            click_to_move_cursor: false,
+            block_selection_mods: Mod::CONTROL_MASK,
+            block_selection: None,
+            confirm_multiline_paste: false,
+            child_exited: false,
+            on_child_exit: None,
+            on_font_size_change: None,
+            font_size_changed: false,
+            font_size_step: 1.0,
+            bindings: bindings::default_bindings(),
+            copy_paste_mode: CopyPasteMode::default(),
+            clipboard_access: ClipboardAccess::None,
+            search: None,
+            on_title_change: Some(set_title.clone()),
+            title_mode: TitleMode::EscapeSequence,
+            title: None,
+            output_title: None,
+            foreground_pid: None,
+            bell_mode: BellMode::Audible,
+            visual_bell_duration,
+            last_bell: None,
+            on_bell: None,
+            desktop_notifications: false,
+            on_notification: None,
+            cursor_blink: gtk::Settings::get_default().map_or(true, |settings| settings.get_property_gtk_cursor_blink()),
+            cursor_visible: true,
+            blink_timer: None,
+            focused: glarea.has_focus(),
+            im: im.clone(),
+            ime_enabled: true,
+            url_detector: Some(UrlDetector::default()),
+            hyperlinks: Vec::new(),
+            urls: Vec::new(),
+            hover_point: None,
+            url_press: None,
+            context_menu: context_menu.clone(),
+            copy_item: copy_item.clone(),
+            paste_item: paste_item.clone(),
+            resize_timer: None,
+            pending_size: None,
+            on_resize: None,
+            show_size: None,
+            grid_size,
+            on_scroll: None,
+            scroll_position: (0, grid_size.1),
+            max_fps: None,
+            last_frame: None,
+            frame_scheduled: Rc::new(Cell::new(false)),
+            color_mode: ColorMode::default(),
+            cursor_color: CursorColor::Reverse,
+            on_activity: None,
+            activity_reported: false,
+            on_silence: None,
+            silence_timeout: Duration::from_secs(10),
+            silence_timer: None,
+            scrollback_limit: widget_options.scrollback_lines,
+            padding: widget_options.padding,
+            word_separators: widget_options.word_separators.clone(),
+            background_opacity: None,
+            on_content_change: None,
+            content_change_timer: None,
+            autoscroll_timer: None,
+            drag_position: (0.0, 0.0),
         });
+        if let Some(ref on_ready) = widget_options.on_ready {
+            on_ready();
+        }
     }));
 
-    glarea.connect_unrealize(clone!(state => move |_widget| {
-        let mut state = state.borrow_mut();
-        *state = None;
+    glarea.connect_unrealize(clone!(state, config => move |glarea| {
+        // the display's GL resources are freed on drop
+        glarea.make_current();
+        if let Some(state) = state.replace(None) {
+            *config.borrow_mut() = Some(state.shutdown());
+        }
+        unregister_widget(id);
     }));
 
-    glarea.connect_render(clone!(state, im => move |_glarea, _glctx| {
+    glarea.connect_render(clone!(state, im => move |glarea, _glctx| {
         let state_cell = &state;
         let mut state = state_cell.borrow_mut();
-        let mut quit = false;
+        let mut font_size_callback = None;
+        let mut title_callback = None;
+        let mut bell_callback = None;
+        let mut resize_callback = None;
+        let mut scroll_callback = None;
+        let mut redraw_again = false;
         if let Some(ref mut state) = *state {
+            let had_output = mem::replace(&mut state.output_since_render, false);
+            if had_output {
+                // the output may have scrolled the lines the block selection is on
+                state.block_selection = None;
+            }
+            process_events(state, glarea);
+            // usually picked up as the output arrives, this is for title_mode changes
+            title_callback = state.update_title();
             let mut terminal = state.terminal.lock();
-            for event in state.event_queue.drain(..) {
-                match event {
-                    Event::Blank => (),
-                    Event::CharInput(c, IsControlHeld(is_ctrl)) => {
-                        let len = c.len_utf8();
-                        let mut bytes = Vec::with_capacity(len);
-                        unsafe {
-                            bytes.set_len(len);
-                            c.encode_utf8(&mut bytes[..]);
-                        }
-                        if is_ctrl {
-                            for ch in bytes.iter_mut() {
-                                if *ch >= 0x40 && *ch < 0x80 {
-                                    *ch = *ch & !0x60;
-                                }
-                            }
-                        }
-                        use alacritty::event::Notify;
-                        state.loop_notifier.notify(bytes);
-                    },
-                    Event::StrInput(s) => {
-                        use alacritty::event::Notify;
-                        state.loop_notifier.notify(s.as_bytes().to_vec());
-                    },
-                    Event::StringInput(s) => {
-                        use alacritty::event::Notify;
-                        state.loop_notifier.notify(s.as_bytes().to_vec());
-                    },
-                    Event::WindowResized(w, h) => {
-                        state.display.command_channel().send(DisplayCommand::NewSize(w, h)).expect("send new size");
-                        terminal.dirty = true;
-                    },
-                    Event::HiDPIFactorChanged(dpr) => {
-                        // state.display.update_glyph_cache(&state.config, Some(fac))
-                        // ^^^ bad somehow? Is the channel really necessary
-                        state.display.command_channel().send(DisplayCommand::NewHiDPIFactor(dpr)).expect("send new dpr");
-                        terminal.dirty = true;
-                    },
-                    Event::ChangeFontSize(delta) => {
-                        terminal.change_font_size(delta.into());
-                    },
-                    Event::ResetFontSize => {
-                        terminal.reset_font_size();
-                    }
-                }
+            if had_output && state.scroll_on_output {
+                terminal.scroll_display(Scroll::Bottom);
+            }
+            if state.font_size_changed {
+                state.font_size_changed = false;
+                font_size_callback = state.on_font_size_change.clone().map(|cb| (cb, terminal.font_size.as_f32_pts()));
             }
-            if let Some(title) = terminal.get_next_title() {
-                header_bar.set_title(&*title);
+            if terminal.next_is_urgent.take() == Some(true) {
+                bell_callback = state.ring_bell();
             }
-            if terminal.needs_draw() {
+            // the visual bell is animated, keep drawing until it's done
+            if state.bell_mode.is_visual() && !terminal.visual_bell.completed() {
+                terminal.dirty = true;
+                redraw_again = true;
+            }
+            if terminal.needs_draw() && !state.frame_capped() {
+                state.last_frame = Some(Instant::now());
                 let (x, y) = state.display.current_xim_spot(&terminal);
                 let &SizeInfo { cell_width, cell_height, .. } = state.display.size();
                 im.set_cursor_location(&gtk::Rectangle {
                     x: x.into(), y: y.into(), width: cell_width as i32, height: cell_height as i32
                 });
                 state.display.handle_resize(&mut terminal, &state.config, &mut [&mut state.pty_fd]);
-                drop(terminal); // mutex unlock
-                state.display.draw(&state.terminal, &state.config, true);
-            }
-            if process_should_exit() {
-                quit = true;
+                // the cell size changes with the font and the DPI, and the minimum size with it
+                let (min_cols, min_rows) = MIN_GRID_SIZE;
+                let min_size = logical_grid_size(state.display.size(), glarea, min_cols, min_rows);
+                if glarea.get_size_request() != min_size {
+                    glarea.set_size_request(min_size.0, min_size.1);
+                }
+                let new_grid_size = grid_size(state);
+                if new_grid_size != state.grid_size {
+                    state.grid_size = new_grid_size;
+                    // resizing rewraps and moves the lines, the block selection's are gone
+                    state.block_selection = None;
+                    resize_callback = Some((state.show_size.clone(), state.on_resize.clone(), new_grid_size));
+                }
+                let new_scroll_position = terminal_scroll_position(&terminal);
+                if new_scroll_position != state.scroll_position {
+                    state.scroll_position = new_scroll_position;
+                    scroll_callback = state.on_scroll.clone().map(|cb| (cb, new_scroll_position));
+                }
+                // the links come first, so they win where the detector sees a URL too
+                state.urls = url::find_hyperlinks(terminal.grid(), &state.hyperlinks);
+                if let Some(ref detector) = state.url_detector {
+                    state.urls.extend(detector.scan(terminal.grid()));
+                }
+                // like the cursor, the hovered URL is underlined just for this frame
+                let mut overlay = Overlay::new();
+                if let Some(hovered) = state.hover_point.and_then(|p| url::url_at(&state.urls, p)) {
+                    url::underline(terminal.grid_mut(), hovered, &mut overlay);
+                }
+                if let Some(block) = active_block(&terminal, state.block_selection.as_ref()) {
+                    block.highlight(terminal.grid_mut(), &mut overlay);
+                }
+                color::downsample(terminal.grid_mut(), state.color_mode, &mut overlay);
+                // blinking hides the cursor just for this frame, unless the program already hid it
+                // NOTE: Alacritty parses DECSCUSR's blinking styles to the steady ones, so
+                // programs can change the cursor's shape but can't turn blinking on or off
+                use alacritty::ansi::{Handler, Mode};
+                let hide_cursor = !state.cursor_visible && terminal.mode().contains(TermMode::SHOW_CURSOR);
+                if hide_cursor {
+                    terminal.unset_mode(Mode::ShowCursor);
+                }
+                // the lock is held until everything is undone, so that the IO thread can't
+                // print into the changed cells (they'd be put back over its output)
+                state.display.draw_locked(&mut terminal, &state.config, state.focused);
+                if hide_cursor {
+                    terminal.set_mode(Mode::ShowCursor);
+                }
+                overlay.undo(terminal.grid_mut());
             }
         }
         drop(state);
-        if quit {
-            let state = state_cell.replace(None).expect("state");
-            let _ = state.io_thread.join();
-            window.destroy();
+        if redraw_again {
+            glarea.queue_draw();
+        }
+        // callbacks are called without the state borrowed, so that they can use it
+        if let Some(on_bell) = bell_callback {
+            on_bell();
+        }
+        if let Some((on_title_change, title)) = title_callback {
+            on_title_change(&title);
+        }
+        if let Some((on_font_size_change, size)) = font_size_callback {
+            on_font_size_change(size);
+        }
+        if let Some((show_size, on_resize, (cols, rows))) = resize_callback {
+            if let Some(show_size) = show_size {
+                show_size(cols, rows);
+            }
+            if let Some(on_resize) = on_resize {
+                on_resize(cols, rows);
+            }
+        }
+        if let Some((on_scroll, (offset, total))) = scroll_callback {
+            on_scroll(offset, total);
         }
         Inhibit(false)
     }));
 
     glarea.connect_resize(clone!(state => move |glarea, w, h| {
+        let state_rc = &state;
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
-            state.event_queue.push(Event::WindowResized(w as u32, h as u32));
+            // the first resize of a burst (e.g. dragging the window edge) applies right away,
+            // the last one once it's over, and there's no SIGWINCH storm in between
+            if let Some(resize_timer) = state.resize_timer.take() {
+                glib::source_remove(resize_timer);
+                state.pending_size = Some((w as u32, h as u32));
+            } else {
+                state.event_queue.push(Event::WindowResized(w as u32, h as u32));
+            }
+            state.resize_timer = Some(gtk::timeout_add(RESIZE_DEBOUNCE_MS, clone!(state_rc, glarea => move || {
+                if let Some(ref mut state) = *state_rc.borrow_mut() {
+                    state.resize_timer = None;
+                    if let Some((w, h)) = state.pending_size.take() {
+                        state.event_queue.push(Event::WindowResized(w, h));
+                    }
+                }
+                glarea.queue_draw();
+                glib::Continue(false)
+            })));
         }
         glarea.queue_draw();
     }));
 
     glarea.add_events(gdk::EventMask::KEY_PRESS_MASK.bits() as i32);
+    glarea.add_events((gdk::EventMask::BUTTON_PRESS_MASK | gdk::EventMask::BUTTON_RELEASE_MASK |
+                       gdk::EventMask::POINTER_MOTION_MASK).bits() as i32);
 
     glarea.connect_key_press_event(clone!(state, im => move |glarea, event| {
-        if im.filter_keypress(event) {
+        let kv = event.get_keyval();
+        restart_blinking(&state, glarea);
+        {
+            let mods = event.get_state();
+            let action = state.borrow().as_ref().and_then(|state| {
+                state.bindings.iter().find(|b| b.matches(kv, mods)).map(|b| b.action.clone()).or_else(|| {
+                    bindings::copy_paste_bindings(state.copy_paste_mode).into_iter()
+                        .find(|b| b.matches(kv, mods)).map(|b| b.action)
+                })
+            });
+            if let Some(action) = action {
+                perform_action(&state, glarea, action);
+                glarea.queue_draw();
+                return Inhibit(true);
+            }
+            if let Some(ref mut state) = *state.borrow_mut() {
+                if let Some(bytes) = keys::csi_u(kv, mods).filter(|_| state.csi_u_keys) {
+                    state.event_queue.push(Event::RawBytes(bytes));
+                    process_events(state, glarea);
+                    glarea.queue_draw();
+                    return Inhibit(true);
+                }
+                // the IM would commit the keypad's plain characters instead
+                if terminal_modes(state).application_keypad {
+                    if let Some(bytes) = keys::application_keypad(kv, mods) {
+                        state.event_queue.push(Event::RawBytes(bytes));
+                        process_events(state, glarea);
+                        glarea.queue_draw();
+                        return Inhibit(true);
+                    }
+                }
+            }
+            // input methods tend to swallow these, so handle them first
+            // (this also means Alt-prefixed chars never get composed by the IM)
+            let alt = mods.intersects(Mod::MOD1_MASK | Mod::META_MASK);
+            if alt || mods.contains(Mod::CONTROL_MASK) {
+                if let Some(c) = gdk::keyval_to_unicode(kv).filter(|c| !c.is_control()) {
+                    let mut state = state.borrow_mut();
+                    if let Some(ref mut state) = *state {
+                        let alt_escape = alt && state.alt_sends_escape;
+                        if let Some(bytes) = keys::modified_char(c, mods.contains(Mod::CONTROL_MASK), alt_escape) {
+                            state.event_queue.push(Event::RawBytes(bytes));
+                            process_events(state, glarea);
+                            glarea.queue_draw();
+                            return Inhibit(true);
+                        }
+                    }
+                }
+            }
+        }
+        let ime_enabled = state.borrow().as_ref().map_or(true, |state| state.ime_enabled);
+        if ime_enabled && im.filter_keypress(event) {
             return Inhibit(true);
         }
-        let kv = event.get_keyval();
         trace!("non-IM input: keyval {:?} unicode {:?}", kv, gdk::keyval_to_unicode(kv));
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
-            use gdk::enums::key::*;
             let mods = event.get_state();
-            // TODO: make this dynamically configurable
-            #[allow(non_upper_case_globals)] // they're not mine, why complain here?!
-            state.event_queue.push(match kv {
-                Page_Up | KP_Page_Up if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[5;2~"),
-                Page_Up | KP_Page_Up if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[5;5~"),
-                Page_Up | KP_Page_Up => Event::StrInput("\x1b[5~"),
-                Page_Down | KP_Page_Down if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[6;2~"),
-                Page_Down | KP_Page_Down if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[6;5~"),
-                Page_Down | KP_Page_Down => Event::StrInput("\x1b[6~"),
-                Tab if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[Z"),
-                Insert => Event::StrInput("\x1b[2~"),
-                Delete | KP_Delete => Event::StrInput("\x1b[3~"),
-                Left | KP_Left if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2D"),
-                Left | KP_Left if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3D"),
-                Left | KP_Left if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5D"),
-                Left | KP_Left => Event::StrInput("\x1b[D"),
-                Right | KP_Right if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2C"),
-                Right | KP_Right if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3C"),
-                Right | KP_Right if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5C"),
-                Right | KP_Right => Event::StrInput("\x1b[C"),
-                Up | KP_Up if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2A"),
-                Up | KP_Up if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3A"),
-                Up | KP_Up if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5A"),
-                Up | KP_Up => Event::StrInput("\x1b[A"),
-                Down | KP_Down if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2B"),
-                Down | KP_Down if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;3B"),
-                Down | KP_Down if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5B"),
-                Down | KP_Down => Event::StrInput("\x1b[B"),
-                F1 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2P"),
-                F1 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3P"),
-                F1 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5P"),
-                F1 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6P"),
-                F1 => Event::StrInput("\x1bOP"),
-                F2 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2Q"),
-                F2 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3Q"),
-                F2 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5Q"),
-                F2 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6Q"),
-                F2 => Event::StrInput("\x1bOQ"),
-                F3 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2R"),
-                F3 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3R"),
-                F3 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5R"),
-                F3 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6R"),
-                F3 => Event::StrInput("\x1bOR"),
-                F4 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[1;2S"),
-                F4 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[1;3S"),
-                F4 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[1;5S"),
-                F4 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[1;6S"),
-                F4 => Event::StrInput("\x1bOS"),
-                F5 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[15;2~"),
-                F5 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[15;3~"),
-                F5 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[15;5~"),
-                F5 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[15;6~"),
-                F5 => Event::StrInput("\x1b[15~"),
-                F6 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[17;2~"),
-                F6 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[17;3~"),
-                F6 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[17;5~"),
-                F6 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[17;6~"),
-                F6 => Event::StrInput("\x1b[17~"),
-                F7 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[18;2~"),
-                F7 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[18;3~"),
-                F7 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[18;5~"),
-                F7 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[18;6~"),
-                F7 => Event::StrInput("\x1b[18~"),
-                F8 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[19;2~"),
-                F8 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[19;3~"),
-                F8 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[19;5~"),
-                F8 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[19;6~"),
-                F8 => Event::StrInput("\x1b[19~"),
-                F9 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[20;2~"),
-                F9 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[20;3~"),
-                F9 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[20;5~"),
-                F9 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[20;6~"),
-                F9 => Event::StrInput("\x1b[20~"),
-                F10 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[21;2~"),
-                F10 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[21;3~"),
-                F10 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[21;5~"),
-                F10 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[21;6~"),
-                F10 => Event::StrInput("\x1b[21~"),
-                F11 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[23;2~"),
-                F11 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[23;3~"),
-                F11 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[23;5~"),
-                F11 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[23;6~"),
-                F11 => Event::StrInput("\x1b[23~"),
-                F12 if mods.contains(Mod::SHIFT_MASK) => Event::StrInput("\x1b[24;2~"),
-                F12 if mods.contains(Mod::SUPER_MASK) => Event::StrInput("\x1b[24;3~"),
-                F12 if mods.contains(Mod::CONTROL_MASK) => Event::StrInput("\x1b[24;5~"),
-                F12 if mods.contains(Mod::META_MASK) => Event::StrInput("\x1b[24;6~"),
-                F12 => Event::StrInput("\x1b[24~"),
-                Super_L | Super_R | Hyper_L | Hyper_R | Control_L | Control_R |
-                    Alt_L | Alt_R | Meta_L | Meta_R | Shift_L | Shift_R |
-                    Caps_Lock | Scroll_Lock | Shift_Lock | ModeLock => Event::Blank,
-                _ => Event::CharInput(gdk::keyval_to_unicode(kv).unwrap_or(kv as u8 as char), IsControlHeld(mods.contains(Mod::CONTROL_MASK))),
+            let mode = *state.terminal.lock().mode();
+            state.event_queue.push(if kv == gdk::enums::key::BackSpace {
+                Event::RawBytes(keys::backspace(mods, state.backspace_sends_delete, state.alt_sends_escape))
+            } else if let Some(bytes) = keys::keyval_to_escape(kv, mods, mode) {
+                Event::RawBytes(bytes)
+            } else if let Some(c) = gdk::keyval_to_unicode(kv) {
+                Event::CharInput(c, IsControlHeld(mods.contains(Mod::CONTROL_MASK)))
+            } else {
+                // modifiers and other keys that don't produce anything
+                Event::Blank
             });
+            process_events(state, glarea);
+        }
+        glarea.queue_draw();
+        // prevent tab from switching focus to the top bar
+        Inhibit(kv == gdk::enums::key::Tab || kv == gdk::enums::key::ISO_Left_Tab)
+    }));
+
+    glarea.connect_key_release_event(clone!(state, im => move |_glarea, event| {
+        if state.borrow().as_ref().map_or(true, |state| state.ime_enabled) {
+            let _ = im.filter_keypress(event);
+        }
+        Inhibit(true)
+    }));
+
+    glarea.connect_button_press_event(clone!(state => move |glarea, event| {
+        glarea.grab_focus();
+        // Ctrl+clicking a URL opens it (on release), instead of selecting or reporting anything
+        if event.get_button() == 1 && event.get_state().contains(Mod::CONTROL_MASK) {
+            if let Some(url) = url_under_pointer(&state, event.get_position()) {
+                if let Some(ref mut state) = *state.borrow_mut() {
+                    let (x, y) = event.get_position();
+                    state.url_press = Some((point_at(state, x, y), url));
+                }
+                return Inhibit(true);
+            }
+        }
+        // middle click pastes the primary selection and right click shows the menu,
+        // unless the program wants the clicks
+        let button = event.get_button();
+        if (button == 2 || button == 3) && event.get_event_type() == gdk::EventType::ButtonPress {
+            let reporting = state.borrow().as_ref().map_or(false, |state| terminal_modes(state).mouse_tracking.is_some());
+            if !reporting || event.get_state().contains(Mod::SHIFT_MASK) {
+                if button == 2 {
+                    paste_from(&state, glarea, "PRIMARY");
+                } else {
+                    popup_context_menu(&state, event);
+                }
+                glarea.queue_draw();
+                return Inhibit(true);
+            }
+        }
+        push_mouse_event(&state, glarea, event, ButtonState::Pressed);
+        Inhibit(false)
+    }));
+
+    glarea.connect_button_release_event(clone!(state => move |glarea, event| {
+        // the release always goes through, so that drags and reports that started
+        // without Ctrl end properly
+        let url_press = state.borrow_mut().as_mut().and_then(|state| state.url_press.take());
+        if let Some((_, url)) = url_press.filter(|&(_, ref url)| event.get_button() == 1
+            && url_under_pointer(&state, event.get_position()).as_ref() == Some(url)) {
+            if let Err(err) = gtk::show_uri(glarea.get_screen().as_ref(), &url, event.get_time()) {
+                warn!("Could not open {}: {}", url, err);
+            }
+        }
+        push_mouse_event(&state, glarea, event, ButtonState::Released);
+        Inhibit(false)
+    }));
+
+    glarea.connect_motion_notify_event(clone!(state => move |glarea, event| {
+        let mods = event.get_state();
+        let scale = glarea.get_scale_factor() as f64;
+        let (x, y) = event.get_position();
+        let button = [(Mod::BUTTON1_MASK, 1), (Mod::BUTTON2_MASK, 2), (Mod::BUTTON3_MASK, 3)].iter()
+            .find(|&&(mask, _)| mods.contains(mask)).map(|&(_, button)| button);
+        if button != Some(1) {
+            hover(&state, glarea, Some(event.get_position()));
+            // this still goes to programs that track all motion, or drags with the other buttons
+            if let Some(ref mut state) = *state.borrow_mut() {
+                state.event_queue.push(Event::MouseMotion { x: x * scale, y: y * scale, button, mods });
+                process_events(state, glarea);
+            }
+            return Inhibit(false);
+        }
+        let state_rc = &state;
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            if state.url_press.as_ref().map_or(false, |&(point, _)| point != point_at(state, x, y)) {
+                state.url_press = None;
+            }
+            state.drag_position = (x * scale, y * scale);
+            state.event_queue.push(Event::MouseMotion { x: x * scale, y: y * scale, button, mods });
+            if state.autoscroll_timer.is_none() && autoscroll_direction(state.display.size(), y * scale) != 0 {
+                state.autoscroll_timer = Some(gtk::timeout_add(AUTOSCROLL_INTERVAL_MS, clone!(state_rc, glarea => move || {
+                    if let Some(ref mut state) = *state_rc.borrow_mut() {
+                        let (x, y) = state.drag_position;
+                        if state.mouse.left_pressed && autoscroll_direction(state.display.size(), y) != 0 {
+                            // the motion extends the selection and scrolls by another line
+                            state.event_queue.push(Event::MouseMotion { x, y, button: Some(1), mods: Mod::empty() });
+                            process_events(state, &glarea);
+                            glarea.queue_draw();
+                            return glib::Continue(true);
+                        }
+                        state.autoscroll_timer = None;
+                    }
+                    glib::Continue(false)
+                })));
+            }
         }
         glarea.queue_draw();
-        Inhibit(kv == gdk::enums::key::Tab) // prevent tab from switching focus to the top bar
+        Inhibit(false)
     }));
 
-    glarea.connect_key_release_event(clone!(im => move |_glarea, event| {
-        let _ = im.filter_keypress(event);
+    glarea.add_events(gdk::EventMask::LEAVE_NOTIFY_MASK.bits() as i32);
+    glarea.connect_leave_notify_event(clone!(state => move |glarea, _event| {
+        hover(&state, glarea, None);
+        Inhibit(false)
+    }));
+
+    glarea.add_events((gdk::EventMask::SCROLL_MASK | gdk::EventMask::SMOOTH_SCROLL_MASK).bits() as i32);
+
+    glarea.connect_scroll_event(clone!(state => move |glarea, event| {
+        let mut state = state.borrow_mut();
+        if let Some(ref mut state) = *state {
+            let delta = match event.get_direction() {
+                gdk::ScrollDirection::Up => 1.0,
+                gdk::ScrollDirection::Down => -1.0,
+                gdk::ScrollDirection::Smooth => -event.get_delta().1,
+                _ => 0.0,
+            };
+            // accumulate fractional trackpad deltas until they add up to whole lines
+            state.scroll_accum += delta;
+            let lines = state.scroll_accum.trunc();
+            if lines != 0.0 {
+                state.scroll_accum -= lines;
+                state.event_queue.push(if event.get_state().contains(Mod::CONTROL_MASK) {
+                    Event::ChangeFontSize(lines as f32 * state.font_size_step)
+                } else {
+                    let scale = glarea.get_scale_factor() as f64;
+                    let (x, y) = event.get_position();
+                    Event::Scroll { lines: lines as i32, x: x * scale, y: y * scale, mods: event.get_state() }
+                });
+                process_events(state, glarea);
+            }
+        }
+        glarea.queue_draw();
         Inhibit(true)
     }));
 
@@ -336,6 +2605,7 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
             state.event_queue.push(Event::StringInput(s.to_owned()));
+            process_events(state, &glarea);
         }
         glarea.queue_draw();
     }));
@@ -344,43 +2614,202 @@ pub fn alacritty_widget(window: gtk::ApplicationWindow, header_bar: gtk::HeaderB
     glarea.drag_dest_add_text_targets();
     glarea.drag_dest_add_uri_targets();
 
-    glarea.connect_drag_data_received(clone!(state => move |_glarea, _dctx, _x, _y, data, _info, _time| {
-        let mut state = state.borrow_mut();
-        if let Some(ref mut state) = *state {
-            let uris = data.get_uris();
-            if uris.len() > 0 {
-                state.event_queue.push(Event::StringInput(uris.iter().map(|u| u.trim().replace("file://", "")).collect::<Vec<_>>().join(" ")));
-            } else if let Some(text) = data.get_text() {
-                state.event_queue.push(Event::StringInput(text.replace("file://", "").trim().to_owned()));
-            }
+    glarea.connect_drag_data_received(clone!(state => move |glarea, _dctx, _x, _y, data, _info, _time| {
+        let uris = data.get_uris();
+        let text = if uris.len() > 0 {
+            Some(uris.iter().map(|u| dropped_uri(u.trim())).collect::<Vec<_>>().join(" "))
+        } else {
+            data.get_text().map(|text| {
+                let text = text.trim();
+                if text.starts_with("file:") { dropped_uri(text) } else { text.to_owned() }
+            })
+        };
+        // dropped text is pasted, so it gets the same confirmation
+        if let Some(text) = text {
+            paste(&state, glarea, text);
         }
     }));
 
+    // e.g. when the window is dragged to a monitor with a different scale
     glarea.connect_property_scale_factor_notify(clone!(state => move |glarea| {
         let mut state = state.borrow_mut();
         if let Some(ref mut state) = *state {
-            state.event_queue.push(Event::HiDPIFactorChanged(glarea.get_scale_factor() as f32));
+            let dpr = glarea.get_scale_factor() as f32;
+            if dpr != state.display.size().dpr {
+                state.event_queue.push(Event::HiDPIFactorChanged(dpr));
+                process_events(state, glarea);
+            }
         }
         glarea.queue_draw();
     }));
 
     glarea.set_can_focus(true);
-    glarea.connect_focus_in_event(clone!(im => move |_glarea, _event| {
-        im.focus_in();
+    glarea.connect_focus_in_event(clone!(state, im => move |glarea, _event| {
+        if state.borrow().as_ref().map_or(true, |state| state.ime_enabled) {
+            im.focus_in();
+        }
+        if let Some(ref mut state) = *state.borrow_mut() {
+            state.focused = true;
+            state.activity_reported = false;
+            if let Some(silence_timer) = state.silence_timer.take() {
+                glib::source_remove(silence_timer);
+            }
+            report_focus(state, b"\x1b[I");
+        }
+        restart_blinking(&state, glarea);
+        glarea.queue_draw();
         Inhibit(false)
     }));
-    glarea.connect_focus_out_event(clone!(im => move |_glarea, _event| {
+    glarea.connect_focus_out_event(clone!(state, im => move |glarea, _event| {
         im.focus_out();
+        if let Some(ref mut state) = *state.borrow_mut() {
+            state.focused = false;
+            state.stop_blinking();
+            report_focus(state, b"\x1b[O");
+        }
+        glarea.queue_draw();
         Inhibit(false)
     }));
     glarea.grab_focus();
 
-    GLOBAL.with(clone!(glarea => move |global| {
-        // NOTE: important to store glarea somewhere, adding to window doesn't prevent from
-        // being dropped at the end of the scope https://github.com/gtk-rs/gtk/issues/637
-        // (conveniently, we need to store it for the notifier here)
-        *global.borrow_mut() = Some(glarea);
-    }));
-
     (glarea, state)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn stopped_threads_are_joined() {
+        // like IO threads, these run until they're told to stop
+        let threads = (0..50).map(|_| {
+            let (tx, rx) = mpsc::channel::<()>();
+            (tx, thread::spawn(move || { let _ = rx.recv(); }))
+        }).collect::<Vec<_>>();
+        for (tx, thread) in threads {
+            drop(tx);
+            assert_eq!(join_timeout(thread, Duration::from_secs(5)), Some(true));
+        }
+    }
+
+    #[test]
+    fn stuck_threads_are_left_behind() {
+        let (tx, rx) = mpsc::channel::<()>();
+        let thread = thread::spawn(move || { let _ = rx.recv(); });
+        assert_eq!(join_timeout(thread, Duration::from_millis(50)), None);
+        drop(tx);
+    }
+
+    /// Tests with PTYs take turns, so that they don't see each other's fds
+    static PTY_TEST: AtomicBool = AtomicBool::new(false);
+
+    struct PtyTurn;
+
+    impl PtyTurn {
+        fn take() -> PtyTurn {
+            while PTY_TEST.swap(true, Ordering::Acquire) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            PtyTurn
+        }
+    }
+
+    impl Drop for PtyTurn {
+        fn drop(&mut self) {
+            PTY_TEST.store(false, Ordering::Release);
+        }
+    }
+
+    /// Starts a child the way the widget does, with 80x24 cells
+    fn spawn(options: &cli::Options, config: &mut Config, env: &HashMap<String, String>)
+             -> (Arc<FairMutex<Term>>, event_loop::Notifier, JoinHandle<(EventLoop<TappedPty>, event_loop::State)>) {
+        let size = crate::headless::size_info(80, 24);
+        let terminal = Arc::new(FairMutex::new(Term::new(config, size)));
+        let (_, loop_notifier, io_thread) = spawn_child(
+            0, config, options, env, &terminal, &size, &Arc::new(Tap::new()), &Arc::new(AtomicBool::new(false)));
+        (terminal, loop_notifier, io_thread)
+    }
+
+    #[test]
+    fn stopped_children_leave_no_fds_behind() {
+        let _turn = PtyTurn::take();
+        let open_fds = || fs::read_dir("/proc/self/fd").unwrap().count();
+        let spawn_and_stop = || {
+            let (_, loop_notifier, io_thread) = spawn(&cli::Options::default(), &mut Config::default(), &HashMap::new());
+            stop_io_thread(&loop_notifier, io_thread);
+        };
+        // whatever is opened once and kept (like GLib's main context for the wakeups)
+        spawn_and_stop();
+        let before = open_fds();
+        for _ in 0..20 {
+            spawn_and_stop();
+        }
+        assert_eq!(open_fds(), before);
+    }
+
+    /// What `$name` is for a child started by `spawn_child` with the config's `env`
+    /// and the widget's, as read off its PTY
+    fn child_sees(config_env: &HashMap<String, String>, env: &HashMap<String, String>, name: &str) -> String {
+        let _turn = PtyTurn::take();
+        let mut options = cli::Options::default();
+        let script = format!("printf '[%s]' \"${}\"", name);
+        options.command = Some(Shell::new_with_args("sh".to_owned(), vec!["-c".to_owned(), script]));
+        let mut config = Config::default();
+        config.set_env(config_env.clone());
+        let (terminal, loop_notifier, io_thread) = spawn(&options, &mut config, env);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut output = String::new();
+        while !output.ends_with(']') && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+            let terminal = terminal.lock();
+            let grid = terminal.grid();
+            output = text::lines_text(grid, (0..grid.num_lines().0).rev(), true).trim().to_owned();
+        }
+        stop_io_thread(&loop_notifier, io_thread);
+        assert!(output.starts_with('[') && output.ends_with(']'), "unexpected output {:?}", output);
+        output[1..output.len() - 1].to_owned()
+    }
+
+    #[test]
+    fn children_get_term_and_colorterm() {
+        assert_eq!(child_sees(&HashMap::new(), &HashMap::new(), "TERM"), TERM);
+        assert_eq!(child_sees(&HashMap::new(), &HashMap::new(), "COLORTERM"), "truecolor");
+    }
+
+    #[test]
+    fn the_widgets_env_wins_over_the_configs() {
+        let config_env = [("TERM".to_owned(), "screen".to_owned()), ("EDITOR".to_owned(), "vi".to_owned())]
+            .iter().cloned().collect();
+        let env = [("TERM".to_owned(), "xterm".to_owned())].iter().cloned().collect();
+        assert_eq!(child_sees(&config_env, &env, "TERM"), "xterm");
+        assert_eq!(child_sees(&config_env, &env, "EDITOR"), "vi");
+    }
+
+    #[test]
+    fn only_the_visual_bell_modes_flash() {
+        let configured = Duration::from_millis(300);
+        assert_eq!(bell_duration(BellMode::Audible, configured), Duration::from_millis(0));
+        assert_eq!(bell_duration(BellMode::None, configured), Duration::from_millis(0));
+        assert_eq!(bell_duration(BellMode::Visual, configured), configured);
+        // even when the config doesn't flash
+        assert_eq!(bell_duration(BellMode::Both, Duration::from_millis(0)), DEFAULT_VISUAL_BELL_DURATION);
+    }
+
+    #[test]
+    fn clicks_move_the_cursor_within_its_wrapped_line_only() {
+        use crate::headless::HeadlessTerminal;
+        let mut terminal = HeadlessTerminal::new(10, 4);
+        // a prompt line wrapped onto a second one, the cursor at its end
+        terminal.feed(b"old\r\n$ 0123456789ab");
+        let point = |line, col| Point::new(Line(line), Column(col));
+        assert_eq!(arrows_to(terminal.term(), point(2, 3)), b"\x1b[D".to_vec());
+        // back onto the line it wrapped from
+        assert_eq!(arrows_to(terminal.term(), point(1, 9)), b"\x1b[D\x1b[D\x1b[D\x1b[D\x1b[D".to_vec());
+        assert_eq!(arrows_to(terminal.term(), point(0, 0)), vec![]);
+        assert_eq!(arrows_to(terminal.term(), point(3, 0)), vec![]);
+    }
+}